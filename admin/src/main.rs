@@ -0,0 +1,441 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+//! GNStore admin command line tool
+//! Manage users and inspect data directly in the
+//! storage, without hand editing the files under data/.
+//! Do not run it while the website binary is running,
+//! as both would write the same storage files.
+
+extern crate chrono;
+extern crate core_lib;
+extern crate storaget;
+
+use chrono::prelude::*;
+use core_lib::model::*;
+use std::env;
+use std::fmt::Debug;
+use std::io::{self, BufRead, Write};
+use std::process;
+use storaget::*;
+
+const USAGE: &str = "Usage: gnstore-admin <command>
+
+Commands:
+    user list
+    user new <username> <name> <email> [role..]
+    user passwd <username>          (reads the new password from stdin)
+    user roles <username> <role..>  (admin, staff, cashier, customer)
+    user disable <username>         (also logs out every session)
+    user enable <username>
+    customer list
+    issue list
+    stats
+
+ENV:
+    GNSTORE_DATA    data folder path, default: data";
+
+type CliResult = Result<(), String>;
+
+/// Parsed command line
+#[derive(Debug, PartialEq)]
+enum Command {
+    UserList,
+    UserNew {
+        userid: String,
+        name: String,
+        email: String,
+        roles: Vec<Role>,
+    },
+    UserPasswd(String),
+    UserRoles(String, Vec<Role>),
+    UserSetDisabled(String, bool),
+    CustomerList,
+    IssueList,
+    Stats,
+}
+
+fn data_path(name: &str) -> String {
+    format!(
+        "{}/{}",
+        env::var("GNSTORE_DATA").unwrap_or("data".to_owned()),
+        name
+    )
+}
+
+fn storage_error<E: Debug>(err: E) -> String {
+    format!("Storage error: {:?}", err)
+}
+
+fn format_date(date: DateTime<Utc>) -> String {
+    date.format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn roles_to_string(roles: &[Role]) -> String {
+    roles
+        .iter()
+        .map(|r| format!("{:?}", r).to_lowercase())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn parse_roles(roles: &[String]) -> Result<Vec<Role>, String> {
+    roles
+        .iter()
+        .map(|r| r.parse::<Role>().map_err(|err| format!("{}", err)))
+        .collect()
+}
+
+fn read_password() -> Result<String, String> {
+    print!("New password: ");
+    io::stdout().flush().map_err(|err| format!("{}", err))?;
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|err| format!("{}", err))?;
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn user_list() -> CliResult {
+    let users = Storage::load_or_init::<User>(&data_path("users")).map_err(storage_error)?;
+    println!(
+        "{:<20} {:<30} {:<30} {:<20} {}",
+        "ID", "NAME", "EMAIL", "ROLES", "STATUS"
+    );
+    for user in users.into_iter() {
+        user.get(|u| {
+            println!(
+                "{:<20} {:<30} {:<30} {:<20} {}",
+                u.get_user_id(),
+                u.get_user_name(),
+                u.get_user_email(),
+                roles_to_string(&u.get_roles()),
+                if u.is_disabled() {
+                    "disabled"
+                } else {
+                    "active"
+                }
+            )
+        });
+    }
+    Ok(())
+}
+
+fn user_new(userid: &str, name: &str, email: &str, roles: &[Role]) -> CliResult {
+    let users = Storage::load_or_init::<User>(&data_path("users")).map_err(storage_error)?;
+    let mut user = User::new(
+        userid.to_owned(),
+        name.to_owned(),
+        email.to_owned(),
+        "".to_owned(),
+        "admin-cli".to_owned(),
+    )
+    .map_err(|err| format!("{}", err))?;
+    if users.get_by_id(user.get_user_id()).is_ok() {
+        return Err(format!("User {} already exists", user.get_user_id()));
+    }
+    if !roles.is_empty() {
+        user.set_roles(roles.to_vec());
+    }
+    user.set_password(read_password()?)
+        .map_err(|err| format!("{}", err))?;
+    users.add_to_storage(user.clone()).map_err(storage_error)?;
+    println!("User {} created", user.get_user_id());
+    Ok(())
+}
+
+fn user_passwd(userid: &str) -> CliResult {
+    let users = Storage::load_or_init::<User>(&data_path("users")).map_err(storage_error)?;
+    let user = users
+        .get_by_id(userid)
+        .map_err(|_| format!("User {} not found", userid))?;
+    let password = read_password()?;
+    user.update(|u| u.set_password(password.clone()))
+        .map_err(|err| format!("{}", err))?;
+    println!("Password updated for {}", userid);
+    Ok(())
+}
+
+fn user_roles(userid: &str, roles: &[Role]) -> CliResult {
+    let users = Storage::load_or_init::<User>(&data_path("users")).map_err(storage_error)?;
+    let user = users
+        .get_by_id(userid)
+        .map_err(|_| format!("User {} not found", userid))?;
+    user.update(|u| u.set_roles(roles.to_vec()));
+    println!("Roles updated for {}: {}", userid, roles_to_string(roles));
+    Ok(())
+}
+
+fn user_set_disabled(userid: &str, disabled: bool) -> CliResult {
+    let users = Storage::load_or_init::<User>(&data_path("users")).map_err(storage_error)?;
+    let user = users
+        .get_by_id(userid)
+        .map_err(|_| format!("User {} not found", userid))?;
+    if disabled {
+        user.update(|u| u.disable());
+        // Log out every session of the user
+        let sessions =
+            Storage::load_or_init::<Session>(&data_path("sessions")).map_err(storage_error)?;
+        sessions
+            .into_iter()
+            .filter(|s| s.get(|s| s.get_userid() == userid && !s.is_revoked()))
+            .for_each(|s| {
+                let _ = s.update(|s| s.revoke());
+            });
+        println!("User {} disabled", userid);
+    } else {
+        user.update(|u| u.enable());
+        println!("User {} enabled", userid);
+    }
+    Ok(())
+}
+
+fn customer_list() -> CliResult {
+    let customers =
+        Storage::load_or_init::<Customer>(&data_path("customers")).map_err(storage_error)?;
+    println!("{:<12} {:<40} {:<30} {}", "ID", "NAME", "EMAIL", "CREATED");
+    for customer in customers.into_iter() {
+        customer.get(|c| {
+            println!(
+                "{:<12} {:<40} {:<30} {}",
+                c.get_id(),
+                c.get_name(),
+                c.get_email(),
+                format_date(c.get_date_created())
+            )
+        });
+    }
+    Ok(())
+}
+
+fn issue_list() -> CliResult {
+    let issues = Storage::load_or_init::<Issue>(&data_path("issues")).map_err(storage_error)?;
+    let mut issues = issues
+        .into_iter()
+        .map(|i| i.clone_data())
+        .collect::<Vec<Issue>>();
+    issues.sort_by(|a, b| b.get_date_created().cmp(&a.get_date_created()));
+    println!(
+        "{:<7} {:<12} {:<7} {:<20} {:<17} {}",
+        "NUMBER", "ID", "STATUS", "ASSIGNED TO", "CREATED", "TITLE"
+    );
+    for issue in issues {
+        println!(
+            "{:<7} {:<12} {:<7} {:<20} {:<17} {}",
+            format!("#{}", issue.get_number()),
+            issue.get_id(),
+            if issue.get_is_open() {
+                "open"
+            } else {
+                "closed"
+            },
+            issue.get_assigned_to(),
+            format_date(issue.get_date_created()),
+            issue.get_title()
+        );
+    }
+    Ok(())
+}
+
+fn stats() -> CliResult {
+    let users = Storage::load_or_init::<User>(&data_path("users")).map_err(storage_error)?;
+    let customers =
+        Storage::load_or_init::<Customer>(&data_path("customers")).map_err(storage_error)?;
+    let issues = Storage::load_or_init::<Issue>(&data_path("issues")).map_err(storage_error)?;
+    let sessions =
+        Storage::load_or_init::<Session>(&data_path("sessions")).map_err(storage_error)?;
+    let notifications = Storage::load_or_init::<NotificationContainer>(&data_path("notifications"))
+        .map_err(storage_error)?;
+    let now = Utc::now();
+    let user_total = users.into_iter().count();
+    let user_disabled = users
+        .into_iter()
+        .filter(|u| u.get(|u| u.is_disabled()))
+        .count();
+    let issue_total = issues.into_iter().count();
+    let issue_open = issues
+        .into_iter()
+        .filter(|i| i.get(|i| i.get_is_open()))
+        .count();
+    let session_total = sessions.into_iter().count();
+    let session_active = sessions
+        .into_iter()
+        .filter(|s| s.get(|s| s.is_active_at(now)))
+        .count();
+    let notification_total: usize = notifications
+        .into_iter()
+        .map(|n| n.get(|n| n.get_notifications().len()))
+        .sum();
+    let notification_new: usize = notifications
+        .into_iter()
+        .map(|n| {
+            n.get(|n| {
+                n.get_notifications()
+                    .iter()
+                    .filter(|n| n.get_is_new())
+                    .count()
+            })
+        })
+        .sum();
+    println!("Users:         {} ({} disabled)", user_total, user_disabled);
+    println!("Customers:     {}", customers.into_iter().count());
+    println!("Issues:        {} ({} open)", issue_total, issue_open);
+    println!(
+        "Sessions:      {} ({} active)",
+        session_total, session_active
+    );
+    println!(
+        "Notifications: {} ({} new)",
+        notification_total, notification_new
+    );
+    Ok(())
+}
+
+fn parse_command(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    let roles = |roles: &[&str]| -> Result<Vec<Role>, String> {
+        parse_roles(&roles.iter().map(|r| r.to_string()).collect::<Vec<String>>())
+    };
+    match args.as_slice() {
+        ["user", "list"] => Ok(Command::UserList),
+        ["user", "new", userid, name, email, rest @ ..] => Ok(Command::UserNew {
+            userid: userid.to_string(),
+            name: name.to_string(),
+            email: email.to_string(),
+            roles: roles(rest)?,
+        }),
+        ["user", "passwd", userid] => Ok(Command::UserPasswd(userid.to_string())),
+        ["user", "roles", userid, rest @ ..] if !rest.is_empty() => {
+            Ok(Command::UserRoles(userid.to_string(), roles(rest)?))
+        }
+        ["user", "disable", userid] => Ok(Command::UserSetDisabled(userid.to_string(), true)),
+        ["user", "enable", userid] => Ok(Command::UserSetDisabled(userid.to_string(), false)),
+        ["customer", "list"] => Ok(Command::CustomerList),
+        ["issue", "list"] => Ok(Command::IssueList),
+        ["stats"] => Ok(Command::Stats),
+        _ => Err(USAGE.to_owned()),
+    }
+}
+
+fn run(args: &[String]) -> CliResult {
+    match parse_command(args)? {
+        Command::UserList => user_list(),
+        Command::UserNew {
+            userid,
+            name,
+            email,
+            roles,
+        } => user_new(&userid, &name, &email, &roles),
+        Command::UserPasswd(userid) => user_passwd(&userid),
+        Command::UserRoles(userid, roles) => user_roles(&userid, &roles),
+        Command::UserSetDisabled(userid, disabled) => user_set_disabled(&userid, disabled),
+        Command::CustomerList => customer_list(),
+        Command::IssueList => issue_list(),
+        Command::Stats => stats(),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(msg) = run(&args) {
+        eprintln!("{}", msg);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(&args("user list")), Ok(Command::UserList));
+        assert_eq!(parse_command(&args("stats")), Ok(Command::Stats));
+        assert_eq!(
+            parse_command(&args("user new demo Demo demo@user.com admin cashier")),
+            Ok(Command::UserNew {
+                userid: "demo".to_owned(),
+                name: "Demo".to_owned(),
+                email: "demo@user.com".to_owned(),
+                roles: vec![Role::Admin, Role::Cashier],
+            })
+        );
+        assert_eq!(
+            parse_command(&args("user roles demo Staff")),
+            Ok(Command::UserRoles("demo".to_owned(), vec![Role::Staff]))
+        );
+        assert_eq!(
+            parse_command(&args("user disable demo")),
+            Ok(Command::UserSetDisabled("demo".to_owned(), true))
+        );
+        assert_eq!(
+            parse_command(&args("user enable demo")),
+            Ok(Command::UserSetDisabled("demo".to_owned(), false))
+        );
+        assert_eq!(
+            parse_command(&args("user passwd demo")),
+            Ok(Command::UserPasswd("demo".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_parse_command_invalid() {
+        assert_eq!(parse_command(&args("")), Err(USAGE.to_owned()));
+        assert_eq!(parse_command(&args("user")), Err(USAGE.to_owned()));
+        assert_eq!(
+            parse_command(&args("user new demo Demo")),
+            Err(USAGE.to_owned())
+        );
+        assert_eq!(
+            parse_command(&args("user roles demo")),
+            Err(USAGE.to_owned())
+        );
+        assert_eq!(parse_command(&args("user passwd")), Err(USAGE.to_owned()));
+        assert_eq!(
+            parse_command(&args("issue list all")),
+            Err(USAGE.to_owned())
+        );
+        // Unknown role is reported, not the usage
+        let err = parse_command(&args("user roles demo boss")).unwrap_err();
+        assert_eq!(err.contains("boss"), true);
+    }
+
+    #[test]
+    fn test_roles() {
+        assert_eq!(roles_to_string(&[]), "");
+        assert_eq!(
+            roles_to_string(&[Role::Admin, Role::Staff, Role::Cashier, Role::Customer]),
+            "admin,staff,cashier,customer"
+        );
+        assert_eq!(
+            parse_roles(&args("Admin staff CASHIER")),
+            Ok(vec![Role::Admin, Role::Staff, Role::Cashier])
+        );
+        assert_eq!(parse_roles(&args("admin root")).is_err(), true);
+        // Round trip
+        let roles = parse_roles(&args("customer admin")).unwrap();
+        assert_eq!(
+            parse_roles(&args(&roles_to_string(&roles).replace(",", " "))),
+            Ok(roles)
+        );
+    }
+}
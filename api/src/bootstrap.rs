@@ -0,0 +1,229 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::prelude::ApiError;
+use core_lib::model::{
+    Counter, Issue, IssueLabel, Role, User, DEFAULT_BACKGROUND_COLOR, DEFAULT_TEXT_COLOR,
+    ISSUE_COUNTER,
+};
+use core_lib::password::generate_random_password;
+use std::env;
+use std::sync::Mutex;
+use storaget::*;
+
+/// One-time setup token
+/// Holds (userid, token) while the first admin has no password.
+/// It lives only in memory, and it is printed to stdout at startup.
+pub struct SetupToken(Mutex<Option<(String, String)>>);
+
+impl SetupToken {
+    pub fn none() -> Self {
+        SetupToken(Mutex::new(None))
+    }
+    fn new(userid: String, token: String) -> Self {
+        SetupToken(Mutex::new(Some((userid, token))))
+    }
+    /// Check the token, and if it matches, then returns the userid
+    /// without invalidating the token.
+    pub fn peek(&self, token: &str) -> Option<String> {
+        match &*self.0.lock().unwrap() {
+            Some((userid, t)) if !token.is_empty() && t == token => Some(userid.clone()),
+            _ => None,
+        }
+    }
+    /// Check the token, and if it matches, then returns the userid
+    /// and invalidates the token, so it can be used only once.
+    pub fn take(&self, token: &str) -> Option<String> {
+        let mut inner = self.0.lock().unwrap();
+        let matches = match &*inner {
+            Some((_, t)) => !token.is_empty() && t == token,
+            None => false,
+        };
+        if matches {
+            inner.take().map(|(userid, _)| userid)
+        } else {
+            None
+        }
+    }
+}
+
+/// Bootstrap the first admin user
+/// If there is no active admin user (empty storage, or users migrated
+/// from before roles), we create an admin user from ENV
+/// (ADMIN_USERNAME, ADMIN_NAME, ADMIN_EMAIL, ADMIN_PASSWORD).
+/// If ADMIN_USERNAME is an existing user, it is promoted to admin instead.
+/// If no password is provided, or an admin has still no password,
+/// we print a one-time setup token to set it through the API.
+pub fn bootstrap_admin(users: &Storage<User>) -> Result<SetupToken, ApiError> {
+    let has_admin = users
+        .into_iter()
+        .any(|u| u.get(|u| u.has_role(Role::Admin) && !u.is_disabled()));
+    let username = env::var("ADMIN_USERNAME")
+        .unwrap_or("admin".to_owned())
+        .to_lowercase();
+    if !has_admin && users.get_by_id(&username).is_ok() {
+        users.get_by_id(&username)?.update(|u| {
+            let mut roles = u.get_roles();
+            roles.push(Role::Admin);
+            u.set_roles(roles);
+            u.enable();
+        });
+        println!("No admin user found, user {} promoted to admin", username);
+    } else if !has_admin {
+        let mut admin = User::new(
+            username,
+            env::var("ADMIN_NAME").unwrap_or("Adminisztrátor".to_owned()),
+            env::var("ADMIN_EMAIL").unwrap_or("admin@localhost.local".to_owned()),
+            "".to_owned(),
+            "system".to_owned(),
+        )?;
+        admin.set_roles(vec![Role::Admin]);
+        if let Ok(password) = env::var("ADMIN_PASSWORD") {
+            admin.set_password(password)?;
+        }
+        users.add_to_storage(admin.clone())?;
+        println!("Initial admin user created: {}", admin.get_user_id());
+    }
+    // Looking for an admin without password
+    let admin = users
+        .into_iter()
+        .map(|u| u.clone_data())
+        .find(|u| u.has_role(Role::Admin) && u.get_password_hash().is_empty());
+    match admin {
+        Some(admin) => {
+            let token = generate_random_password(Some(32))?;
+            println!(
+                "Admin setup token for user {}: {}\nUse it once at POST /api/setup to set the admin password.",
+                admin.get_user_id(),
+                token
+            );
+            Ok(SetupToken::new(admin.get_user_id().to_string(), token))
+        }
+        None => Ok(SetupToken::none()),
+    }
+}
+
+/// Issue numbering migration
+/// Creates the issue counter, and gives numbers to the issues
+/// created before numbering, in order of their creation.
+pub fn bootstrap_issue_numbers(
+    issues: &Storage<Issue>,
+    counters: &Storage<Counter>,
+) -> Result<(), ApiError> {
+    if counters.get_by_id(ISSUE_COUNTER).is_err() {
+        counters.add_to_storage(Counter::new(ISSUE_COUNTER))?;
+    }
+    let counter = counters.get_by_id(ISSUE_COUNTER)?;
+    // Counter must be ahead of every existing number
+    let max_number = issues
+        .into_iter()
+        .map(|i| i.get(|i| i.get_number()))
+        .max()
+        .unwrap_or(0);
+    counter.update(|c| c.set_at_least(max_number));
+    let mut unnumbered = issues
+        .into_iter()
+        .filter(|i| i.get(|i| i.get_number() == 0))
+        .map(|i| i.get(|i| (i.get_date_created(), i.get_id().to_string())))
+        .collect::<Vec<_>>();
+    unnumbered.sort();
+    for (_, id) in &unnumbered {
+        let number = counter.update(|c| c.next_value());
+        issues.get_by_id(id)?.update(|i| i.set_number(number));
+    }
+    if !unnumbered.is_empty() {
+        println!("Issue numbers given to {} issues", unnumbered.len());
+    }
+    Ok(())
+}
+
+/// Label registry migration
+/// Moves the free-form labels of the issues into the label registry.
+/// Labels with the same subject (case-insensitive) become one label,
+/// keeping the colors of the first one found.
+pub fn bootstrap_labels(
+    issues: &Storage<Issue>,
+    labels: &Storage<IssueLabel>,
+) -> Result<(), ApiError> {
+    let legacy = issues
+        .into_iter()
+        .filter(|i| i.get(|i| !i.get_labels().is_empty()))
+        .map(|i| i.get(|i| (i.get_id().to_string(), i.get_created_by(), i.get_labels())))
+        .collect::<Vec<_>>();
+    for (issue_id, created_by, issue_labels) in &legacy {
+        let mut label_ids: Vec<String> = Vec::new();
+        for legacy_label in issue_labels {
+            let subject = legacy_label.get_subject();
+            let existing = labels
+                .into_iter()
+                .find(|l| l.get(|l| !l.is_deleted() && l.has_subject(&subject)))
+                .map(|l| l.get(|l| l.get_id().to_string()));
+            let label_id = match existing {
+                Some(id) => id,
+                None => {
+                    let label = IssueLabel::new(
+                        &subject,
+                        &legacy_label.get_text_color(),
+                        &legacy_label.get_background_color(),
+                        created_by,
+                    )
+                    .or_else(|_| {
+                        IssueLabel::new(
+                            &subject,
+                            DEFAULT_TEXT_COLOR,
+                            DEFAULT_BACKGROUND_COLOR,
+                            created_by,
+                        )
+                    })?;
+                    let id = label.get_id().to_string();
+                    labels.add_to_storage(label)?;
+                    id
+                }
+            };
+            if !label_ids.contains(&label_id) {
+                label_ids.push(label_id);
+            }
+        }
+        issues
+            .get_by_id(issue_id)?
+            .update(|i| i.migrate_labels(label_ids));
+    }
+    if !legacy.is_empty() {
+        println!("Labels moved to the registry from {} issues", legacy.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_token() {
+        let token = SetupToken::new("admin".into(), "secret".into());
+        assert_eq!(token.peek(""), None);
+        assert_eq!(token.peek("wrong"), None);
+        // Peek does not use up the token
+        assert_eq!(token.peek("secret"), Some("admin".to_string()));
+        assert_eq!(token.peek("secret"), Some("admin".to_string()));
+        assert_eq!(token.take("wrong"), None);
+        assert_eq!(token.take("secret"), Some("admin".to_string()));
+        assert_eq!(token.peek("secret"), None);
+        assert_eq!(token.take("secret"), None);
+        assert_eq!(SetupToken::none().peek("secret"), None);
+    }
+}
@@ -20,7 +20,7 @@ use crate::prelude::*;
 use crate::DataLoad;
 use chrono::prelude::*;
 use core_lib::customer::*;
-use core_lib::model::Customer;
+use core_lib::model::{Customer, Permission};
 use core_lib::prelude::AppResult;
 use rocket::State;
 use rocket_contrib::json::Json;
@@ -70,9 +70,10 @@ impl From<&Customer> for CustomerResponse {
 
 #[get("/customer/all")]
 pub fn customer_all_get(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
 ) -> Result<StatusOk<Vec<CustomerResponse>>, ApiError> {
+    user.require(Permission::CustomerRead)?;
     let res = data
         .inner()
         .customers
@@ -84,10 +85,11 @@ pub fn customer_all_get(
 
 #[get("/customer/<id>")]
 pub fn customer_id_get(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<CustomerResponse>, ApiError> {
+    user.require(Permission::CustomerRead)?;
     if let Ok(customer) = data.inner().customers.get_by_id(&id) {
         return Ok(StatusOk(customer.get(|c| c.into())));
     }
@@ -96,11 +98,12 @@ pub fn customer_id_get(
 
 #[post("/customer/<id>", data = "<form>")]
 pub fn customer_id_post(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
     id: String,
     form: Json<CustomerResponse>,
 ) -> Result<StatusOk<CustomerResponse>, ApiError> {
+    user.require(Permission::CustomerWrite)?;
     println!("ID: {}", id);
     if let Ok(customer) = data.inner().customers.get_by_id(&id) {
         match customer.update(|c| -> AppResult<Customer> {
@@ -139,6 +142,7 @@ pub fn customer_new_post(
     c: Json<NewCustomer>,
     data: State<DataLoad>,
 ) -> Result<StatusOk<CustomerResponse>, ApiError> {
+    user.require(Permission::CustomerWrite)?;
     let new_customer = Customer::new(
         generate_customer_id(),
         c.name.clone(),
@@ -0,0 +1,140 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::guard::Login;
+use crate::prelude::*;
+use crate::DataLoad;
+use chrono::prelude::*;
+use core_lib::model::{OutboxEmail, OutboxStatus, Permission};
+use core_lib::template::{RenderedEmail, Template};
+use rocket::State;
+use serde::Serialize;
+use storaget::*;
+
+/**
+ * Outbox email without its body
+ * Bodies can contain reset links, so
+ * we never list them.
+ */
+#[derive(Serialize, Debug, Clone)]
+pub struct OutboxEmailResponse {
+    id: String,
+    to: String,
+    subject: String,
+    status: OutboxStatus,
+    attempts: u32,
+    last_error: Option<String>,
+    date_created: DateTime<Utc>,
+}
+
+impl From<&OutboxEmail> for OutboxEmailResponse {
+    fn from(e: &OutboxEmail) -> Self {
+        OutboxEmailResponse {
+            id: e.get_id().to_string(),
+            to: e.get_to().to_string(),
+            subject: e.get_subject().to_string(),
+            status: e.get_status(),
+            attempts: e.get_attempts(),
+            last_error: e.get_last_error().map(|e| e.to_string()),
+            date_created: e.get_date_created(),
+        }
+    }
+}
+
+#[get("/email/all")]
+pub fn email_all_get(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<Vec<OutboxEmailResponse>>, ApiError> {
+    user.require(Permission::EmailManage)?;
+    let mut res = data
+        .inner()
+        .emails
+        .into_iter()
+        .map(|e| e.get(|e| e.into()))
+        .collect::<Vec<OutboxEmailResponse>>();
+    res.sort_by(|a, b| b.date_created.cmp(&a.date_created));
+    Ok(StatusOk(res))
+}
+
+/**
+ * Failed emails (dead letters)
+ */
+#[get("/email/failed")]
+pub fn email_failed_get(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<Vec<OutboxEmailResponse>>, ApiError> {
+    user.require(Permission::EmailManage)?;
+    let mut res = data
+        .inner()
+        .emails
+        .into_iter()
+        .filter(|e| e.get(|e| e.get_status() == OutboxStatus::Failed))
+        .map(|e| e.get(|e| e.into()))
+        .collect::<Vec<OutboxEmailResponse>>();
+    res.sort_by(|a, b| b.date_created.cmp(&a.date_created));
+    Ok(StatusOk(res))
+}
+
+/**
+ * Retry a failed email
+ * The worker picks it up at its next round.
+ */
+#[post("/email/<id>/retry")]
+pub fn email_id_retry_post(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+) -> Result<StatusOk<OutboxEmailResponse>, ApiError> {
+    user.require(Permission::EmailManage)?;
+    match data.inner().emails.get_by_id(&id) {
+        Ok(email) => {
+            email.update(|e| e.retry(Utc::now()))?;
+            Ok(StatusOk(email.get(|e| e.into())))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
+
+/**
+ * Available email template names
+ */
+#[get("/email/template/all")]
+pub fn email_template_all_get(user: Login) -> Result<StatusOk<Vec<String>>, ApiError> {
+    user.require(Permission::EmailManage)?;
+    Ok(StatusOk(
+        Template::all()
+            .iter()
+            .map(|t| t.name().to_string())
+            .collect(),
+    ))
+}
+
+/**
+ * Preview email template
+ * Renders the template with sample data.
+ */
+#[get("/email/template/<name>/preview")]
+pub fn email_template_preview_get(
+    user: Login,
+    name: String,
+) -> Result<StatusOk<RenderedEmail>, ApiError> {
+    user.require(Permission::EmailManage)?;
+    let template = name.parse::<Template>()?;
+    Ok(StatusOk(template.render(&template.sample_vars())?))
+}
@@ -15,17 +15,28 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::controller::label::{resolve_labels, LabelResponse};
 use crate::guard::Login;
+use crate::notify::{self, NF};
+use crate::outbox;
 use crate::prelude::*;
 use crate::DataLoad;
 use chrono::prelude::*;
 use core_lib::error::Error;
+use core_lib::issue::{
+    added_issue_references, added_mentions, parse_date, parse_issue_number, parse_issue_references,
+    parse_mentions, IssueFilter, IssueSort,
+};
+use core_lib::markdown::{self, References, TaskProgress};
 use core_lib::model::*;
 use core_lib::prelude::AppResult;
+use core_lib::template::Template;
+use rocket::request::Form;
 use rocket::State;
 use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
-use storaget::Storage;
+use std::collections::BTreeMap;
+use std::env;
 use storaget::*;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -43,11 +54,12 @@ pub struct NewIssue {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IssueShort {
     id: String,
+    number: usize,
     title: String,
     description: String,
     created_by: String,
     pub date_created: DateTime<Utc>,
-    labels: Vec<Label>,
+    labels: Vec<LabelResponse>,
     assigned_to: String,
     comment_count: usize,
     is_open: bool,
@@ -56,16 +68,27 @@ pub struct IssueShort {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IssueLong {
     id: String,
+    number: usize,
     title: String,
     description: String,
     created_by: String,
     date_created: DateTime<Utc>,
-    labels: Vec<Label>,
+    labels: Vec<LabelResponse>,
     assigned_to: String,
     comment_count: usize,
     events: Vec<Event>,
     followed_by: Vec<String>,
     is_open: bool,
+    /**
+     * Rendered and sanitized markdown
+     */
+    description_html: String,
+    /**
+     * Comment ID => rendered and sanitized markdown
+     * Deleted comments are not included
+     */
+    comments_html: BTreeMap<usize, String>,
+    task_progress: TaskProgress,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -74,21 +97,33 @@ pub struct CommentNew {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct LabelScheme {
-    subject: String,
-    text_color: String,
-    background_color: String,
+pub struct TitleForm {
+    title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DescriptionForm {
+    description: String,
 }
 
-impl From<Issue> for IssueShort {
-    fn from(issue: Issue) -> Self {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LabelRef {
+    /**
+     * IssueLabel ID
+     */
+    id: String,
+}
+
+impl IssueShort {
+    fn new(data: &DataLoad, issue: Issue) -> Self {
         IssueShort {
             id: issue.get_id().to_string(),
+            number: issue.get_number(),
             title: issue.get_title(),
             description: issue.get_description(),
             created_by: issue.get_created_by(),
             date_created: issue.get_date_created(),
-            labels: issue.get_labels(),
+            labels: resolve_labels(data, issue.get_label_ids()),
             assigned_to: issue.get_assigned_to(),
             comment_count: issue.get_comment_count(),
             is_open: issue.get_is_open(),
@@ -96,32 +131,47 @@ impl From<Issue> for IssueShort {
     }
 }
 
-impl From<Issue> for IssueLong {
-    fn from(issue: Issue) -> Self {
+impl IssueLong {
+    fn new(data: &DataLoad, issue: Issue) -> Self {
         IssueLong {
             id: issue.get_id().to_string(),
+            number: issue.get_number(),
             title: issue.get_title(),
             description: issue.get_description(),
             created_by: issue.get_created_by(),
             date_created: issue.get_date_created(),
-            labels: issue.get_labels(),
+            labels: resolve_labels(data, issue.get_label_ids()),
             assigned_to: issue.get_assigned_to(),
             comment_count: issue.get_comment_count(),
-            events: issue.get_events(),
+            events: issue.get_visible_events(),
             followed_by: issue.get_followed_by(),
             is_open: issue.get_is_open(),
+            description_html: render_markdown(data, &issue.get_description()),
+            comments_html: issue
+                .get_comments()
+                .iter()
+                .map(|c| (c.get_id(), render_markdown(data, c.get_text())))
+                .collect(),
+            task_progress: issue.get_task_progress(),
         }
     }
 }
 
-impl From<Label> for LabelScheme {
-    fn from(label: Label) -> Self {
-        LabelScheme {
-            subject: label.get_subject(),
-            text_color: label.get_text_color(),
-            background_color: label.get_background_color(),
-        }
-    }
+/// Render markdown with the existing
+/// user and issue references linked
+fn render_markdown(data: &DataLoad, text: &str) -> String {
+    let references = References {
+        users: valid_mentions(data, text),
+        issues: parse_issue_references(text)
+            .into_iter()
+            .filter(|number| {
+                data.issues
+                    .into_iter()
+                    .any(|i| i.get(|i| i.get_number() == *number))
+            })
+            .collect(),
+    };
+    markdown::render(text, &references)
 }
 
 #[put("/issue/new", data = "<form>")]
@@ -130,41 +180,169 @@ pub fn user_new_put(
     data: State<DataLoad>,
     form: Json<NewIssue>,
 ) -> Result<StatusOk<IssueShort>, ApiError> {
-    let new_issue = Issue::new(
+    user.require(Permission::IssueWrite)?;
+    let mut new_issue = Issue::new(
         form.title.clone(),
         form.description.clone(),
         user.userid().to_string(),
     );
+    new_issue.set_number(next_issue_number(data.inner())?);
+    // Mentioned users follow the issue
+    let mentions = valid_mentions(data.inner(), &form.description);
+    for mentioned in &mentions {
+        new_issue.follow(mentioned.clone());
+    }
     data.inner().issues.add_to_storage(new_issue.clone())?;
-    Ok(StatusOk(new_issue.into()))
+    notify_mentions(data.inner(), &user, &new_issue, &mentions, None);
+    Ok(StatusOk(IssueShort::new(data.inner(), new_issue)))
+}
+
+// Default and max page size of the issue list
+const PAGE_LIMIT_DEFAULT: usize = 50;
+const PAGE_LIMIT_MAX: usize = 200;
+
+/**
+ * Issue list query parameters
+ * All of them are optional.
+ */
+#[derive(FromForm, Debug)]
+pub struct IssueQuery {
+    /**
+     * open, closed or all (default)
+     */
+    status: Option<String>,
+    assigned_to: Option<String>,
+    created_by: Option<String>,
+    /**
+     * Label ID
+     */
+    label: Option<String>,
+    followed_by: Option<String>,
+    /**
+     * YYYY-MM-DD, inclusive
+     */
+    created_from: Option<String>,
+    created_to: Option<String>,
+    /**
+     * Free text search in title, description and comments
+     */
+    q: Option<String>,
+    /**
+     * created (default), updated, comments or title
+     */
+    sort: Option<String>,
+    /**
+     * asc or desc (default)
+     */
+    order: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl IssueQuery {
+    fn to_filter(&self) -> Result<IssueFilter, ApiError> {
+        Ok(IssueFilter {
+            is_open: match self.status.as_ref().map(|s| s.as_str()) {
+                Some("open") => Some(true),
+                Some("closed") => Some(false),
+                Some("all") | None => None,
+                Some(status) => {
+                    return Err(ApiError::BadRequest(format!(
+                        "Ismeretlen státusz: {}",
+                        status
+                    )))
+                }
+            },
+            assigned_to: self.assigned_to.clone(),
+            created_by: self.created_by.clone(),
+            label: self.label.clone(),
+            followed_by: self.followed_by.clone(),
+            created_from: match &self.created_from {
+                Some(date) => Some(parse_date(date)?),
+                None => None,
+            },
+            created_to: match &self.created_to {
+                Some(date) => Some(parse_date(date)?),
+                None => None,
+            },
+            text: self.q.clone(),
+        })
+    }
 }
 
-#[get("/issue/all")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IssuePage {
+    issues: Vec<IssueShort>,
+    /**
+     * Number of all matching issues
+     */
+    total: usize,
+    offset: usize,
+    limit: usize,
+}
+
+#[get("/issue/all?<query..>")]
 pub fn issue_all_get(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
-) -> Result<StatusOk<Vec<IssueShort>>, ApiError> {
-    let mut res = data
+    query: Form<IssueQuery>,
+) -> Result<StatusOk<IssuePage>, ApiError> {
+    user.require(Permission::IssueRead)?;
+    let filter = query.to_filter()?;
+    let sort = match &query.sort {
+        Some(sort) => sort.parse::<IssueSort>()?,
+        None => IssueSort::default(),
+    };
+    let descending = match query.order.as_ref().map(|o| o.as_str()) {
+        Some("asc") => false,
+        Some("desc") | None => true,
+        Some(order) => {
+            return Err(ApiError::BadRequest(format!(
+                "Ismeretlen sorrend: {}",
+                order
+            )))
+        }
+    };
+    let offset = query.offset.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(PAGE_LIMIT_DEFAULT)
+        .min(PAGE_LIMIT_MAX)
+        .max(1);
+    // Clone only the matching issues
+    let mut issues = data
         .inner()
         .issues
         .into_iter()
-        .map(|d| d.get(|i| i.clone().into()))
+        .filter(|d| d.get(|i| filter.matches(i)))
+        .map(|d| d.clone_data())
+        .collect::<Vec<Issue>>();
+    sort.sort(&mut issues, descending);
+    let total = issues.len();
+    let issues = issues
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|i| IssueShort::new(data.inner(), i))
         .collect::<Vec<IssueShort>>();
-    /*
-     * Order result by date
-     */
-    res.sort_by(|a, b| b.date_created.cmp(&a.date_created));
-    Ok(StatusOk(res))
+    Ok(StatusOk(IssuePage {
+        issues,
+        total,
+        offset,
+        limit,
+    }))
 }
 
 #[get("/issue/<id>")]
 pub fn issue_id_get(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueRead)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     let issue = data.inner().issues.get_by_id(&id)?.clone_data();
-    Ok(StatusOk(issue.clone().into()))
+    Ok(StatusOk(IssueLong::new(data.inner(), issue)))
 }
 
 #[post("/issue/<id>/follow")]
@@ -173,13 +351,15 @@ pub fn issue_id_follow_post(
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueRead)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
             let mod_issue = issue.update(|i| -> Issue {
                 i.follow(user.userid().to_string());
                 i.clone()
             });
-            Ok(StatusOk(mod_issue.into()))
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -191,13 +371,15 @@ pub fn issue_id_unfollow_post(
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueRead)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
             let mod_issue = issue.update(|i| -> Issue {
                 i.unfollow(user.userid().to_string());
                 i.clone()
             });
-            Ok(StatusOk(mod_issue.into()))
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -210,6 +392,8 @@ pub fn issue_id_assign_to_post(
     id: String,
     assigned_to: String,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueAssign)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     // Validate, assigned_to userid exist
     if let Err(_) = data.inner().users.get_by_id(&assigned_to) {
         return Err(ApiError::BadRequest(
@@ -218,65 +402,231 @@ pub fn issue_id_assign_to_post(
     }
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
-            let mod_issue = issue.update(|i| -> Issue {
+            let (event_count, mod_issue) = issue.update(|i| -> (usize, Issue) {
+                let event_count = i.get_event_count();
                 i.set_assigned_to(assigned_to.clone(), user.userid().to_string());
-                i.clone()
+                (event_count, i.clone())
             });
-            if user.userid() != &assigned_to {
+            notify_followers(data.inner(), &user, &mod_issue, event_count);
+            if user.userid() != &assigned_to && mod_issue.get_event_count() > event_count {
                 // Send notification to the assigned user
-                let mut notification = Notification::new(format!(
-                    "Hozzárendeltek a következő issue-hoz: {}",
-                    mod_issue.get_title()
-                ));
-                notification.set_location(Location::Issue {
-                    id: id.clone(),
-                    section: None,
-                });
-                let _ = data
-                    .inner()
-                    .notifications
-                    .notify_user(&assigned_to, notification);
+                let notification = Notification::from_kind(
+                    NotificationKind::IssueAssigned {
+                        actor: user.actor(),
+                        title: mod_issue.get_title(),
+                    },
+                    Location::Issue {
+                        id: id.clone(),
+                        section: mod_issue.get_event_section(event_count),
+                    },
+                );
+                let _ = data.inner().notify_user(&assigned_to, notification);
+                // Assignment has its own email template,
+                // so we do not use notify::notify here.
+                let wants_email = notify::wants_email(
+                    data.inner(),
+                    &assigned_to,
+                    NotificationCategory::Assignment,
+                );
+                if let (true, Ok(assignee)) =
+                    (wants_email, data.inner().users.get_by_id(&assigned_to))
+                {
+                    let result = assignee.get(|u: &User| {
+                        send_assigned_email(data.inner(), u, user.name(), &mod_issue)
+                    });
+                    if let Err(err) = result {
+                        eprintln!("Issue assigned email error: {:?}", err);
+                    }
+                }
             }
-            Ok(StatusOk(mod_issue.into()))
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
 }
 
-pub trait NF {
-    fn notify_user(&self, userid: &str, notification: Notification) -> AppResult<()>;
+/// Next sequential issue number
+fn next_issue_number(data: &DataLoad) -> Result<usize, ApiError> {
+    Ok(data
+        .counters
+        .get_by_id(ISSUE_COUNTER)?
+        .update(|c| c.next_value()))
+}
+
+/// Find issue ID by its ID or by its number (142 or #142)
+fn resolve_issue_id(data: &DataLoad, id: &str) -> Result<String, ApiError> {
+    if data.issues.get_by_id(id).is_ok() {
+        return Ok(id.to_string());
+    }
+    let number = parse_issue_number(id).ok_or(ApiError::NotFound)?;
+    data.issues
+        .into_iter()
+        .find(|i| i.get(|i| i.get_number() == number))
+        .map(|i| i.get(|i| i.get_id().to_string()))
+        .ok_or(ApiError::NotFound)
+}
+
+/// Parse @userid mentions from the text
+/// and keep only the existing users.
+fn valid_mentions(data: &DataLoad, text: &str) -> Vec<String> {
+    parse_mentions(text)
+        .into_iter()
+        .filter(|userid| data.users.get_by_id(userid).is_ok())
+        .collect()
 }
 
-impl NF for Storage<NotificationContainer> {
-    fn notify_user(&self, userid: &str, notification: Notification) -> AppResult<()> {
-        match self.get_by_id(userid) {
-            Ok(container) => {
-                container.update(|c| c.add(notification.clone()));
-                Ok(())
+/// Notify the mentioned users, except the actor
+fn notify_mentions(
+    data: &DataLoad,
+    actor: &Login,
+    issue: &Issue,
+    mentions: &[String],
+    section: Option<String>,
+) {
+    let notification = Notification::from_kind(
+        NotificationKind::IssueMentioned {
+            actor: actor.actor(),
+            title: issue.get_title(),
+        },
+        Location::Issue {
+            id: issue.get_id().to_string(),
+            section,
+        },
+    );
+    for mentioned in mentions.iter().filter(|m| *m != actor.userid()) {
+        if let Err(err) = notify::notify(data, mentioned, notification.clone()) {
+            eprintln!("Issue mention notification error: {:?}", err);
+        }
+    }
+}
+
+/// Notify the followers about the events
+/// created after the first @from events of the issue.
+/// The actor is never notified about its own change,
+/// the new assignee gets its own assignment notification,
+/// and mentioned users get their mention notification instead.
+/// Read @from in the same update as the change, otherwise events
+/// of a concurrent update would be attributed to the actor.
+fn notify_followers(data: &DataLoad, actor: &Login, issue: &Issue, from: usize) {
+    for (index, event) in issue.get_events_from(from) {
+        let title = issue.get_title();
+        let kind = match &event.kind {
+            EventKind::NewComment(_) => NotificationKind::IssueCommented {
+                actor: actor.actor(),
+                title,
+            },
+            EventKind::LabelAttached(label_id) => NotificationKind::IssueLabelAdded {
+                actor: actor.actor(),
+                title,
+                label: label_subject(data, label_id),
+            },
+            EventKind::LabelDetached(label_id) => NotificationKind::IssueLabelRemoved {
+                actor: actor.actor(),
+                title,
+                label: label_subject(data, label_id),
+            },
+            EventKind::LabelAdded(label) => NotificationKind::IssueLabelAdded {
+                actor: actor.actor(),
+                title,
+                label: label.get_subject(),
+            },
+            EventKind::LabelRemoved(label) => NotificationKind::IssueLabelRemoved {
+                actor: actor.actor(),
+                title,
+                label: label.get_subject(),
+            },
+            EventKind::AssignedTo(assigned_to) => NotificationKind::IssueReassigned {
+                actor: actor.actor(),
+                title,
+                assigned_to: assigned_to.clone(),
+            },
+            EventKind::Closed => NotificationKind::IssueClosed {
+                actor: actor.actor(),
+                title,
+            },
+            EventKind::Opened => NotificationKind::IssueOpened {
+                actor: actor.actor(),
+                title,
+            },
+            // References are added to other issues,
+            // and edits do not notify
+            EventKind::Referenced { .. }
+            | EventKind::TitleChanged { .. }
+            | EventKind::DescriptionChanged { .. }
+            | EventKind::CommentEdited { .. }
+            | EventKind::CommentDeleted { .. } => continue,
+        };
+        let notification = Notification::from_kind(
+            kind,
+            Location::Issue {
+                id: issue.get_id().to_string(),
+                section: issue.get_event_section(index),
+            },
+        );
+        for follower in issue.get_followers_except(actor.userid()) {
+            let skip = match &event.kind {
+                EventKind::AssignedTo(assignee) => *assignee == follower,
+                EventKind::NewComment(comment) => comment.get_mentions().contains(&follower),
+                _ => false,
+            };
+            if skip {
+                continue;
             }
-            Err(_) => {
-                let mut notification_object = NotificationContainer::new(userid.to_string());
-                notification_object.add(notification.clone());
-                self.add_to_storage(notification_object)?;
-                Ok(())
+            if let Err(err) = notify::notify(data, &follower, notification.clone()) {
+                eprintln!("Issue follower notification error: {:?}", err);
             }
         }
     }
 }
 
+fn label_subject(data: &DataLoad, label_id: &str) -> String {
+    match data.labels.get_by_id(label_id) {
+        Ok(label) => label.get(|l| l.get_subject().to_string()),
+        Err(_) => label_id.to_string(),
+    }
+}
+
+fn send_assigned_email(
+    data: &DataLoad,
+    assignee: &User,
+    assigned_by: &str,
+    issue: &Issue,
+) -> Result<(), ApiError> {
+    let link = format!(
+        "{}/issue/{}",
+        env::var("CLIENT_URL").map_err(core_lib::Error::from)?,
+        issue.get_id()
+    );
+    outbox::enqueue_template(
+        &data.emails,
+        assignee.get_user_email(),
+        Template::IssueAssigned,
+        &[
+            ("name", assignee.get_user_name()),
+            ("assigned_by", assigned_by),
+            ("issue_title", &issue.get_title()),
+            ("link", &link),
+        ],
+    )
+}
+
 #[post("/issue/<id>/close")]
 pub fn issue_id_close_post(
     user: Login,
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
-            let mod_issue = issue.update(|i| -> Issue {
+            let (event_count, mod_issue) = issue.update(|i| -> (usize, Issue) {
+                let event_count = i.get_event_count();
                 i.close_issue(user.userid().to_string());
-                i.clone()
+                (event_count, i.clone())
             });
-            Ok(StatusOk(mod_issue.into()))
+            notify_followers(data.inner(), &user, &mod_issue, event_count);
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -288,13 +638,17 @@ pub fn issue_id_open_post(
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
-            let mod_issue = issue.update(|i| -> Issue {
+            let (event_count, mod_issue) = issue.update(|i| -> (usize, Issue) {
+                let event_count = i.get_event_count();
                 i.open_issue(user.userid().to_string());
-                i.clone()
+                (event_count, i.clone())
             });
-            Ok(StatusOk(mod_issue.into()))
+            notify_followers(data.inner(), &user, &mod_issue, event_count);
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -307,13 +661,207 @@ pub fn issue_id_comment_post(
     id: String,
     form: Json<CommentNew>,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
-            let mod_issue = issue.update(|i| -> Issue {
-                i.add_comment(form.text.clone(), user.userid().to_string());
-                i.clone()
+            let mentions = valid_mentions(data.inner(), &form.text);
+            let (event_count, mod_issue) = issue.update(|i| -> (usize, Issue) {
+                let event_count = i.get_event_count();
+                i.add_comment(
+                    form.text.clone(),
+                    user.userid().to_string(),
+                    mentions.clone(),
+                );
+                (event_count, i.clone())
+            });
+            notify_mentions(
+                data.inner(),
+                &user,
+                &mod_issue,
+                &mentions,
+                mod_issue.get_event_section(event_count),
+            );
+            notify_followers(data.inner(), &user, &mod_issue, event_count);
+            add_references(
+                data.inner(),
+                &user,
+                &mod_issue,
+                mod_issue.get_comment_count(),
+                parse_issue_references(&form.text),
+            );
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
+
+/// Add cross-reference events to the issues
+/// referenced as #number in a comment of the issue.
+/// Comment ID 0 means the issue description.
+fn add_references(
+    data: &DataLoad,
+    actor: &Login,
+    issue: &Issue,
+    comment_id: usize,
+    numbers: Vec<usize>,
+) {
+    for number in numbers {
+        if number == issue.get_number() {
+            continue;
+        }
+        if let Some(referenced) = data
+            .issues
+            .into_iter()
+            .find(|i| i.get(|i| i.get_number() == number))
+        {
+            referenced.update(|i| {
+                i.add_reference(
+                    issue.get_id().to_string(),
+                    issue.get_number(),
+                    comment_id,
+                    actor.userid().to_string(),
+                )
+            });
+        }
+    }
+}
+
+#[put("/issue/<id>/title", data = "<form>")]
+pub fn issue_id_title_put(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+    form: Json<TitleForm>,
+) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
+    match data.inner().issues.get_by_id(&id) {
+        Ok(issue) => {
+            let mod_issue = issue.update(|i| -> AppResult<Issue> {
+                i.edit_title(form.title.clone(), user.userid().to_string())?;
+                Ok(i.clone())
+            });
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue?)))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
+
+#[put("/issue/<id>/description", data = "<form>")]
+pub fn issue_id_description_put(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+    form: Json<DescriptionForm>,
+) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
+    match data.inner().issues.get_by_id(&id) {
+        Ok(issue) => {
+            let mentions = valid_mentions(data.inner(), &form.description);
+            let (previous, mod_issue) = issue.update(|i| -> (Option<String>, Issue) {
+                let previous = i.edit_description(
+                    form.description.clone(),
+                    user.userid().to_string(),
+                    mentions.clone(),
+                );
+                (previous, i.clone())
+            });
+            if let Some(previous) = previous {
+                let added = added_mentions(&previous, &mentions);
+                notify_mentions(data.inner(), &user, &mod_issue, &added, None);
+                add_references(
+                    data.inner(),
+                    &user,
+                    &mod_issue,
+                    0,
+                    added_issue_references(&previous, &form.description),
+                );
+            }
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
+
+/**
+ * Edit comment
+ * Only the author or an admin can edit it.
+ */
+#[put("/issue/<id>/comment/<comment_id>", data = "<form>")]
+pub fn issue_id_comment_put(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+    comment_id: usize,
+    form: Json<CommentNew>,
+) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
+    match data.inner().issues.get_by_id(&id) {
+        Ok(issue) => {
+            let mentions = valid_mentions(data.inner(), &form.text);
+            let (previous, mod_issue) =
+                issue.update(|i| -> AppResult<(Option<String>, Issue)> {
+                    let previous = i.edit_comment(
+                        comment_id,
+                        form.text.clone(),
+                        user.userid().to_string(),
+                        user.has_role(Role::Admin),
+                        mentions.clone(),
+                    )?;
+                    Ok((previous, i.clone()))
+                })?;
+            // Only newly mentioned users and references,
+            // the previous ones were handled already
+            if let Some(previous) = previous {
+                let added = added_mentions(&previous, &mentions);
+                notify_mentions(
+                    data.inner(),
+                    &user,
+                    &mod_issue,
+                    &added,
+                    Some(format!("comment-{}", comment_id)),
+                );
+                add_references(
+                    data.inner(),
+                    &user,
+                    &mod_issue,
+                    comment_id,
+                    added_issue_references(&previous, &form.text),
+                );
+            }
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
+
+/**
+ * Soft delete comment
+ * Only the author or an admin can delete it.
+ */
+#[delete("/issue/<id>/comment/<comment_id>")]
+pub fn issue_id_comment_delete(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+    comment_id: usize,
+) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
+    match data.inner().issues.get_by_id(&id) {
+        Ok(issue) => {
+            let mod_issue = issue.update(|i| -> AppResult<Issue> {
+                i.delete_comment(
+                    comment_id,
+                    user.userid().to_string(),
+                    user.has_role(Role::Admin),
+                )?;
+                Ok(i.clone())
             });
-            Ok(StatusOk(mod_issue.into()))
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue?)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -326,13 +874,15 @@ pub fn issue_id_comment_like_post(
     id: String,
     comment_id: usize,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
             let mod_issue = issue.update(|i| -> AppResult<Issue> {
                 i.like_comment(comment_id, user.userid().to_string())?;
                 Ok(i.clone())
             });
-            Ok(StatusOk(mod_issue?.into()))
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue?)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -345,13 +895,15 @@ pub fn issue_id_comment_dislike_post(
     id: String,
     comment_id: usize,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
             let mod_issue = issue.update(|i| -> AppResult<Issue> {
                 i.dislike_comment(comment_id, user.userid().to_string())?;
                 Ok(i.clone())
             });
-            Ok(StatusOk(mod_issue?.into()))
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue?)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -362,20 +914,28 @@ pub fn issue_id_label_add_post(
     user: Login,
     data: State<DataLoad>,
     id: String,
-    label: Json<LabelScheme>,
+    label: Json<LabelRef>,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
-    let lab = Label::new(
-        label.subject.clone(),
-        label.text_color.clone(),
-        label.background_color.clone(),
-    );
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
+    // Only existing labels can be attached
+    match data.inner().labels.get_by_id(&label.id) {
+        Ok(l) if !l.get(|l| l.is_deleted()) => (),
+        _ => {
+            return Err(ApiError::BadRequest(
+                "A megadott címke nem létezik".to_owned(),
+            ))
+        }
+    }
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
-            let mod_issue = issue.update(|i| -> AppResult<Issue> {
-                i.add_label(lab.clone(), user.userid().to_string());
-                Ok(i.clone())
+            let (event_count, mod_issue) = issue.update(|i| -> (usize, Issue) {
+                let event_count = i.get_event_count();
+                i.attach_label(label.id.clone(), user.userid().to_string());
+                (event_count, i.clone())
             });
-            Ok(StatusOk(mod_issue?.into()))
+            notify_followers(data.inner(), &user, &mod_issue, event_count);
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -386,20 +946,19 @@ pub fn issue_id_label_remove_post(
     user: Login,
     data: State<DataLoad>,
     id: String,
-    label: Json<LabelScheme>,
+    label: Json<LabelRef>,
 ) -> Result<StatusOk<IssueLong>, ApiError> {
-    let lab = Label::new(
-        label.subject.clone(),
-        label.text_color.clone(),
-        label.background_color.clone(),
-    );
+    user.require(Permission::IssueWrite)?;
+    let id = resolve_issue_id(data.inner(), &id)?;
     match data.inner().issues.get_by_id(&id) {
         Ok(issue) => {
-            let mod_issue = issue.update(|i| -> AppResult<Issue> {
-                i.remove_label(lab.clone(), user.userid().to_string());
-                Ok(i.clone())
+            let (event_count, mod_issue) = issue.update(|i| -> (usize, Issue) {
+                let event_count = i.get_event_count();
+                i.detach_label(&label.id, user.userid().to_string());
+                (event_count, i.clone())
             });
-            Ok(StatusOk(mod_issue?.into()))
+            notify_followers(data.inner(), &user, &mod_issue, event_count);
+            Ok(StatusOk(IssueLong::new(data.inner(), mod_issue)))
         }
         Err(_) => Err(ApiError::NotFound),
     }
@@ -411,7 +970,10 @@ pub fn issue_id_label_remove_post(
  * (+) assigned_to
  * (+) coment
  * (+) comment like / dislike
- * ( ) comment notify @ sign detection
- *     and Notification integration
+ * (+) comment notify @ sign detection
+ * (+) Notification integration
+ * (+) #number cross-references
  * (+) close / open
+ * (+) edit title / description / comment
+ * (+) comment soft delete
  */
@@ -0,0 +1,165 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::guard::Login;
+use crate::prelude::*;
+use crate::DataLoad;
+use core_lib::model::*;
+use rocket::State;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use storaget::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LabelResponse {
+    id: String,
+    subject: String,
+    text_color: String,
+    background_color: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LabelForm {
+    subject: String,
+    text_color: String,
+    background_color: String,
+}
+
+impl From<&IssueLabel> for LabelResponse {
+    fn from(label: &IssueLabel) -> Self {
+        LabelResponse {
+            id: label.get_id().to_string(),
+            subject: label.get_subject().to_string(),
+            text_color: label.get_text_color().to_string(),
+            background_color: label.get_background_color().to_string(),
+        }
+    }
+}
+
+/// Resolve label IDs into labels
+/// Deleted and missing labels are skipped.
+pub fn resolve_labels(data: &DataLoad, label_ids: &[String]) -> Vec<LabelResponse> {
+    label_ids
+        .iter()
+        .filter_map(|id| data.labels.get_by_id(id).ok())
+        .filter(|l| l.get(|l| !l.is_deleted()))
+        .map(|l| l.get(|l| l.into()))
+        .collect()
+}
+
+/// Check that no other active label has the same subject
+fn check_subject_free(
+    data: &DataLoad,
+    subject: &str,
+    except: Option<&str>,
+) -> Result<(), ApiError> {
+    let taken = data.labels.into_iter().any(|l| {
+        l.get(|l| !l.is_deleted() && l.has_subject(subject) && Some(l.get_id()) != except)
+    });
+    if taken {
+        return Err(ApiError::BadRequest(
+            "Ilyen nevű címke már létezik!".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+#[get("/label/all")]
+pub fn label_all_get(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<Vec<LabelResponse>>, ApiError> {
+    user.require(Permission::IssueRead)?;
+    let mut res = data
+        .inner()
+        .labels
+        .into_iter()
+        .filter(|l| l.get(|l| !l.is_deleted()))
+        .map(|l| l.get(|l| l.into()))
+        .collect::<Vec<LabelResponse>>();
+    res.sort_by(|a, b| a.subject.to_lowercase().cmp(&b.subject.to_lowercase()));
+    Ok(StatusOk(res))
+}
+
+#[post("/label/new", data = "<form>")]
+pub fn label_new_post(
+    user: Login,
+    data: State<DataLoad>,
+    form: Json<LabelForm>,
+) -> Result<StatusOk<LabelResponse>, ApiError> {
+    user.require(Permission::LabelManage)?;
+    let label = IssueLabel::new(
+        &form.subject,
+        &form.text_color,
+        &form.background_color,
+        user.userid(),
+    )?;
+    check_subject_free(data.inner(), label.get_subject(), None)?;
+    data.inner().labels.add_to_storage(label.clone())?;
+    Ok(StatusOk((&label).into()))
+}
+
+/**
+ * Rename and recolor label
+ * Issues refer to it by ID, so it changes everywhere.
+ */
+#[put("/label/<id>", data = "<form>")]
+pub fn label_id_put(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+    form: Json<LabelForm>,
+) -> Result<StatusOk<LabelResponse>, ApiError> {
+    user.require(Permission::LabelManage)?;
+    let label = match data.inner().labels.get_by_id(&id) {
+        Ok(label) if !label.get(|l| l.is_deleted()) => label,
+        _ => return Err(ApiError::NotFound),
+    };
+    check_subject_free(data.inner(), &form.subject, Some(&id))?;
+    let result = label.update(|l| -> Result<IssueLabel, ApiError> {
+        // Validate both, before changing anything
+        let mut changed = l.clone();
+        changed.rename(&form.subject)?;
+        changed.recolor(&form.text_color, &form.background_color)?;
+        *l = changed;
+        Ok(l.clone())
+    })?;
+    Ok(StatusOk((&result).into()))
+}
+
+/**
+ * Delete label
+ * It is detached from every issue, with an event.
+ */
+#[delete("/label/<id>")]
+pub fn label_id_delete(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+) -> Result<StatusOk<()>, ApiError> {
+    user.require(Permission::LabelManage)?;
+    match data.inner().labels.get_by_id(&id) {
+        Ok(label) if !label.get(|l| l.is_deleted()) => label.update(|l| l.delete()),
+        _ => return Err(ApiError::NotFound),
+    };
+    data.inner()
+        .issues
+        .into_iter()
+        .filter(|i| i.get(|i| i.get_label_ids().contains(&id)))
+        .for_each(|i| i.update(|i| i.detach_label(&id, user.userid().to_string())));
+    Ok(StatusOk(()))
+}
@@ -15,15 +15,22 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::login::create_token;
+use crate::bootstrap::SetupToken;
+use crate::guard::{ClientIp, Login};
+use crate::login::{
+    create_challenge_token, create_token, verify_challenge_token, IpThrottle, TokenConfig,
+    TokenError,
+};
+use crate::outbox;
 use crate::prelude::*;
 use crate::DataLoad;
-use core_lib::model::User;
-use core_lib::password;
+use chrono::prelude::*;
+use core_lib::model::{parse_password_reset_token, parse_refresh_token, Session, User};
 use core_lib::user;
 use rocket::State;
 use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
+use std::env;
 
 #[derive(Serialize, Deserialize)]
 pub struct FormLogin {
@@ -31,19 +38,50 @@ pub struct FormLogin {
     password: Option<String>,
 }
 
+/**
+ * Login result
+ * If the user has two-factor authentication enabled,
+ * we return only a challenge token, and the tokens
+ * are issued by /login/two_factor.
+ */
 #[derive(Serialize, Debug)]
 pub struct UserToken {
     username: String,
-    token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    two_factor_challenge: Option<String>,
+}
+
+/**
+ * Start a new session and issue its tokens
+ */
+fn issue_tokens(
+    data: &DataLoad,
+    config: &TokenConfig,
+    mut session: Session,
+    username: String,
+) -> Result<UserToken, ApiError> {
+    let refresh_token = session.issue_refresh_token()?;
+    data.sessions.add_to_storage(session.clone())?;
+    Ok(UserToken {
+        username,
+        token: Some(create_token(config, &session)?),
+        refresh_token: Some(refresh_token),
+        two_factor_challenge: None,
+    })
 }
 
 #[post("/login", data = "<login>")]
 pub fn post(
     login: Json<FormLogin>,
     data: State<DataLoad>,
+    config: State<TokenConfig>,
+    throttle: State<IpThrottle>,
+    ip: ClientIp,
 ) -> Result<StatusOk<UserToken>, ApiError> {
-    // Temp login to admin
-    // TODO: Remove this part, vulnerable code
     let username = match &login.username {
         Some(username) => username,
         None => return Err(ApiError::BadRequest("Hiányzó felhasználói név".to_owned())),
@@ -52,26 +90,201 @@ pub fn post(
         Some(password) => password,
         None => return Err(ApiError::BadRequest("Hiányzó jelszó".to_owned())),
     };
-    if username == "admin" && password == "admin" {
-        return Ok(StatusOk(UserToken {
-            username: "Admin".to_owned(),
-            token: create_token(&username).unwrap(),
-        }));
+    let now = Utc::now();
+    if throttle.is_locked(ip.0, now) {
+        return Err(ApiError::TooManyRequests);
     }
+    let result = match data.inner().users.get_by_id(&username) {
+        Ok(user) => user
+            .update(|u| core_lib::login(u, &password, config.get_refresh_expires_in()))
+            .map(|session| {
+                (
+                    session,
+                    user.get(|u: &User| (u.get_user_name().to_owned(), u.has_two_factor())),
+                )
+            }),
+        Err(_) => {
+            // Unknown user, but we spend the same time
+            // as with a password check, so the response time
+            // does not tell whether the user exists.
+            let _ = core_lib::password::hash_password(&password);
+            Err(core_lib::Error::BadRequest(
+                "Helytelen belépési adatok".to_owned(),
+            ))
+        }
+    };
+    match result {
+        Ok((session, (name, true))) => {
+            // Password is fine, but we need the second factor
+            // before the session starts.
+            throttle.reset(ip.0);
+            Ok(StatusOk(UserToken {
+                username: name,
+                token: None,
+                refresh_token: None,
+                two_factor_challenge: Some(create_challenge_token(
+                    config.inner(),
+                    session.get_userid(),
+                )?),
+            }))
+        }
+        Ok((session, (name, false))) => {
+            throttle.reset(ip.0);
+            Ok(StatusOk(issue_tokens(
+                data.inner(),
+                config.inner(),
+                session,
+                name,
+            )?))
+        }
+        Err(_) => {
+            throttle.register_failure(ip.0, now);
+            Err(ApiError::BadRequest("Helytelen belépési adatok".to_owned()))
+        }
+    }
+}
 
-    // User exist
-    if let Ok(user) = &data.inner().users.get_by_id(&username) {
-        let hash = user.get(|u| u.get_password_hash().to_owned());
-        let res = password::verify_password_from_hash(&password, &hash)?;
-        if res {
-            return Ok(StatusOk(UserToken {
-                username: user.get(|u: &User| u.get_user_name().to_owned()),
-                token: create_token(&username).unwrap(),
-            }));
+#[derive(Serialize, Deserialize)]
+pub struct FormTwoFactor {
+    challenge: Option<String>,
+    code: Option<String>,
+}
+
+/**
+ * Second login step
+ * Accepts the challenge token from /login with
+ * a TOTP or recovery code, and starts the session.
+ */
+#[post("/login/two_factor", data = "<form>")]
+pub fn two_factor(
+    form: Json<FormTwoFactor>,
+    data: State<DataLoad>,
+    config: State<TokenConfig>,
+    throttle: State<IpThrottle>,
+    ip: ClientIp,
+) -> Result<StatusOk<UserToken>, ApiError> {
+    let challenge = match &form.challenge {
+        Some(challenge) => challenge,
+        None => return Err(ApiError::BadRequest("Hiányzó challenge token".to_owned())),
+    };
+    let code = match &form.code {
+        Some(code) => code,
+        None => return Err(ApiError::BadRequest("Hiányzó ellenőrző kód".to_owned())),
+    };
+    let now = Utc::now();
+    if throttle.is_locked(ip.0, now) {
+        return Err(ApiError::TooManyRequests);
+    }
+    let claims = match verify_challenge_token(config.inner(), challenge) {
+        Ok(claims) => claims,
+        Err(TokenError::Expired) => return Err(ApiError::TokenExpired),
+        Err(TokenError::Invalid) => return Err(ApiError::TokenInvalid),
+    };
+    let user = match data.inner().users.get_by_id(claims.get_userid()) {
+        Ok(user) => user,
+        Err(_) => return Err(ApiError::TokenInvalid),
+    };
+    if user.get(|u: &User| u.is_disabled()) {
+        return Err(ApiError::TokenInvalid);
+    }
+    match user.update(|u| u.verify_two_factor(code, now)) {
+        Ok(_) => {
+            throttle.reset(ip.0);
+            let session = Session::new(
+                claims.get_userid().to_owned(),
+                now + config.get_refresh_expires_in(),
+            );
+            let name = user.get(|u: &User| u.get_user_name().to_owned());
+            Ok(StatusOk(issue_tokens(
+                data.inner(),
+                config.inner(),
+                session,
+                name,
+            )?))
+        }
+        Err(_) => {
+            throttle.register_failure(ip.0, now);
+            Err(ApiError::BadRequest("Hibás ellenőrző kód".to_owned()))
         }
     }
+}
 
-    return Err(ApiError::BadRequest("Helytelen belépési adatok".to_owned()));
+#[derive(Serialize, Deserialize)]
+pub struct FormRefresh {
+    refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RefreshedToken {
+    token: String,
+    refresh_token: String,
+}
+
+/**
+ * Refresh access token
+ * Each refresh token can be used only once, we return
+ * a new one with the new access token. Using an
+ * already used refresh token revokes the whole session.
+ */
+#[post("/login/refresh", data = "<form>")]
+pub fn refresh(
+    form: Json<FormRefresh>,
+    data: State<DataLoad>,
+    config: State<TokenConfig>,
+) -> Result<StatusOk<RefreshedToken>, ApiError> {
+    let refresh_token = match &form.refresh_token {
+        Some(token) => token,
+        None => return Err(ApiError::BadRequest("Hiányzó refresh token".to_owned())),
+    };
+    let session_id = match parse_refresh_token(refresh_token) {
+        Some((session_id, _, _)) => session_id,
+        None => return Err(ApiError::TokenInvalid),
+    };
+    let session = match data.inner().sessions.get_by_id(session_id) {
+        Ok(session) => session,
+        Err(_) => return Err(ApiError::TokenInvalid),
+    };
+    match session.update(|s| s.rotate_refresh_token(refresh_token, Utc::now())) {
+        Ok(new_refresh_token) => Ok(StatusOk(RefreshedToken {
+            token: session.get(|s| create_token(config.inner(), s))?,
+            refresh_token: new_refresh_token,
+        })),
+        Err(_) => Err(ApiError::SessionRevoked),
+    }
+}
+
+#[post("/logout")]
+pub fn logout(user: Login, data: State<DataLoad>) -> Result<StatusOk<()>, ApiError> {
+    match data.inner().sessions.get_by_id(user.token_id()) {
+        Ok(session) => {
+            session.update(|s| core_lib::logout(s))?;
+            Ok(StatusOk(()))
+        }
+        Err(_) => Err(ApiError::Unauthorized),
+    }
+}
+
+/**
+ * Revoke every active session of a user
+ */
+pub fn revoke_user_sessions(data: &DataLoad, userid: &str) {
+    data.sessions
+        .into_iter()
+        .filter(|s| s.get(|s| s.get_userid() == userid && !s.is_revoked()))
+        .for_each(|s| {
+            let _ = s.update(|s| s.revoke());
+        });
+}
+
+/**
+ * Logout from all the devices
+ * Revokes every active session of the user,
+ * including the current one.
+ */
+#[post("/logout/all")]
+pub fn logout_all(user: Login, data: State<DataLoad>) -> Result<StatusOk<()>, ApiError> {
+    revoke_user_sessions(data.inner(), user.userid());
+    Ok(StatusOk(()))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -79,6 +292,12 @@ pub struct FormResetPassword {
     email: Option<String>,
 }
 
+/**
+ * Request password reset email
+ * The response is the same whether the email address
+ * belongs to a user or not, so it cannot be used to
+ * find out registered email addresses.
+ */
 #[post("/login/reset_password", data = "<form>")]
 pub fn reset_password(
     form: Json<FormResetPassword>,
@@ -89,21 +308,105 @@ pub fn reset_password(
         None => return Err(ApiError::BadRequest("Hiányzó email cím".to_owned())),
     };
 
-    let user = match user::get_user_by_email(&data.inner().users, email) {
-        Ok(user) => user,
-        Err(_) => {
+    if let Ok(user) = user::get_user_by_email(&data.inner().users, email) {
+        let result = env::var("CLIENT_URL")
+            .map_err(core_lib::Error::from)
+            .and_then(|client_url| user.update(|u| user::request_password_reset(u, &client_url)))
+            .map_err(ApiError::from)
+            .and_then(|message| outbox::enqueue(&data.inner().emails, message));
+        if let Err(err) = result {
+            eprintln!("Password reset email error: {:?}", err);
+        }
+    }
+    Ok(StatusAccepted(()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FormResetPasswordConfirm {
+    token: Option<String>,
+    password1: Option<String>,
+    password2: Option<String>,
+}
+
+/**
+ * Set new password using the emailed reset token
+ * All the sessions of the user are logged out.
+ */
+#[post("/login/reset_password/confirm", data = "<form>")]
+pub fn reset_password_confirm(
+    form: Json<FormResetPasswordConfirm>,
+    data: State<DataLoad>,
+) -> Result<StatusOk<()>, ApiError> {
+    let (token, password1, password2) = match (&form.token, &form.password1, &form.password2) {
+        (Some(t), Some(p1), Some(p2)) => (t, p1, p2),
+        _ => return Err(ApiError::BadRequest("Hiányzó adatmező!".to_owned())),
+    };
+    if password1 != password2 {
+        return Err(ApiError::BadRequest(
+            "A két jelszó nem egyezik meg egymással".to_owned(),
+        ));
+    }
+    let userid = match parse_password_reset_token(token) {
+        Some((userid, _)) => userid,
+        None => {
             return Err(ApiError::BadRequest(
-                "A felhasználó nem található".to_owned(),
+                "Hibás vagy lejárt jelszó visszaállító token".to_owned(),
             ))
         }
     };
-
-    match &user.update(|u| u.reset_password()) {
-        Ok(()) => return Ok(StatusAccepted(())),
+    let user = match data.inner().users.get_by_id(userid) {
+        Ok(user) => user,
         Err(_) => {
-            return Err(ApiError::InternalError(
-                "Hiba az új jelszó beállításánál".to_owned(),
+            return Err(ApiError::BadRequest(
+                "Hibás vagy lejárt jelszó visszaállító token".to_owned(),
             ))
         }
     };
+    user.update(|u| u.confirm_password_reset(token, password1.clone(), Utc::now()))?;
+    revoke_user_sessions(data.inner(), userid);
+    Ok(StatusOk(()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FormSetup {
+    token: Option<String>,
+    password1: Option<String>,
+    password2: Option<String>,
+}
+
+/**
+ * Set the first admin password
+ * using the one-time setup token printed at startup.
+ */
+#[post("/setup", data = "<form>")]
+pub fn setup(
+    form: Json<FormSetup>,
+    data: State<DataLoad>,
+    setup_token: State<SetupToken>,
+) -> Result<StatusOk<()>, ApiError> {
+    let (token, password1, password2) = match (&form.token, &form.password1, &form.password2) {
+        (Some(t), Some(p1), Some(p2)) => (t, p1, p2),
+        _ => return Err(ApiError::BadRequest("Hiányzó adatmező!".to_owned())),
+    };
+    if password1 != password2 {
+        return Err(ApiError::BadRequest(
+            "A két jelszó nem egyezik meg egymással".to_owned(),
+        ));
+    }
+    let invalid_token =
+        || ApiError::BadRequest("Hibás vagy már felhasznált setup token".to_owned());
+    let userid = setup_token.peek(token).ok_or_else(invalid_token)?;
+    let user = data
+        .inner()
+        .users
+        .get_by_id(&userid)
+        .map_err(|_| ApiError::NotFound)?;
+    // Validate password for this user before we use up the token,
+    // so a rejected password can be retried with the same token
+    user.get(|u| u.validate_password(password1))?;
+    if setup_token.take(token).is_none() {
+        return Err(invalid_token());
+    }
+    user.update(|u| u.set_password(password1.clone()))?;
+    Ok(StatusOk(()))
 }
@@ -16,7 +16,9 @@
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod customer;
+pub mod email;
 pub mod issue;
+pub mod label;
 pub mod login;
 pub mod notification;
 pub mod profile;
@@ -15,13 +15,19 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::guard::Login;
+use crate::guard::{LastEventId, Login, StreamLogin};
+use crate::notify::{
+    self, deleted_event, notification_event, publish_unread, seen_event, unread_event,
+};
 use crate::prelude::*;
+use crate::stream::{Event, EventStream, STREAM_LIFETIME_SECONDS};
 use crate::DataLoad;
 use chrono::prelude::*;
 use core_lib::model::*;
 use rocket::State;
+use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NotificationResponse {
@@ -30,6 +36,14 @@ pub struct NotificationResponse {
     is_new: bool,
     subject: String,
     location: Option<String>,
+    category: NotificationCategory,
+    kind: NotificationKind,
+    target: Option<Location>,
+    /**
+     * Number of collapsed notifications
+     */
+    count: usize,
+    actors: Vec<Actor>,
 }
 
 impl From<&Notification> for NotificationResponse {
@@ -40,49 +54,228 @@ impl From<&Notification> for NotificationResponse {
             is_new: from.get_is_new(),
             subject: from.get_subject().to_string(),
             location: from.get_location(),
+            category: from.get_category(),
+            kind: from.get_kind().clone(),
+            target: from.get_target().cloned(),
+            count: from.get_count(),
+            actors: from.get_actors().clone(),
         }
     }
 }
 
-#[get("/notification")]
+// Default and max page size of the notification list
+const PAGE_LIMIT_DEFAULT: usize = 20;
+const PAGE_LIMIT_MAX: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NotificationPage {
+    notifications: Vec<NotificationResponse>,
+    /**
+     * Cursor of the next page, use it as ?before=
+     * None if this is the last page
+     */
+    next_cursor: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnreadCount {
+    unread: usize,
+}
+
+/**
+ * Notifications, newest first
+ * ?before= cursor from the previous page
+ * ?limit= page size, max 100
+ * ?unread_only=true to skip the seen ones
+ */
+#[get("/notification?<before>&<limit>&<unread_only>")]
 pub fn notification_get(
     user: Login,
     data: State<DataLoad>,
-) -> Result<StatusOk<Vec<NotificationResponse>>, ApiError> {
-    match data.inner().notifications.get_by_id(user.userid()) {
-        Ok(object) => {
-            let mut response = object
-                .get(|n| n.get_notifications().to_vec())
-                .iter()
+    before: Option<usize>,
+    limit: Option<usize>,
+    unread_only: Option<bool>,
+) -> Result<StatusOk<NotificationPage>, ApiError> {
+    let limit = limit
+        .unwrap_or(PAGE_LIMIT_DEFAULT)
+        .min(PAGE_LIMIT_MAX)
+        .max(1);
+    let notifications = match data.inner().notifications.get_by_id(user.userid()) {
+        Ok(object) => object.get(|n| {
+            n.get_page(before, limit, unread_only.unwrap_or(false))
+                .into_iter()
                 .map(|v| v.into())
-                .collect::<Vec<NotificationResponse>>();
-            /*
-             * Order result by date
-             */
-            response.sort_by(|a, b| b.date_created.cmp(&a.date_created));
-            Ok(StatusOk(response))
+                .collect::<Vec<NotificationResponse>>()
+        }),
+        Err(_) => Vec::new(),
+    };
+    let next_cursor = match notifications.last() {
+        Some(last) if notifications.len() == limit => Some(last.id),
+        _ => None,
+    };
+    Ok(StatusOk(NotificationPage {
+        notifications,
+        next_cursor,
+    }))
+}
+
+#[get("/notification/unread")]
+pub fn notification_unread_get(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<UnreadCount>, ApiError> {
+    let unread = match data.inner().notifications.get_by_id(user.userid()) {
+        Ok(container) => container.get(|c| c.get_unread_count()),
+        Err(_) => 0,
+    };
+    Ok(StatusOk(UnreadCount { unread }))
+}
+
+#[put("/notification/seen_all")]
+pub fn notification_seen_all(user: Login, data: State<DataLoad>) -> Result<StatusOk<()>, ApiError> {
+    if let Ok(container) = data.inner().notifications.get_by_id(user.userid()) {
+        for id in container.update(|c| c.set_all_seen()) {
+            data.inner().hub.publish(user.userid(), seen_event(id));
         }
-        Err(_) => Ok(StatusOk(Vec::new())),
+        publish_unread(data.inner(), user.userid());
     }
+    Ok(StatusOk(()))
 }
 
-#[get("/notification/new")]
-pub fn notification_new_get(user: Login, data: State<DataLoad>) -> Result<StatusOk<()>, ApiError> {
-    let mut notification = Notification::new("Hello bello".to_owned());
-    notification.set_location(Location::Raw("Demo location".to_owned()));
-    match data.inner().notifications.get_by_id(user.userid()) {
-        Ok(container) => {
-            container.update(|c| c.add(notification.clone()));
-            Ok(StatusOk(()))
+#[delete("/notification/seen")]
+pub fn notification_seen_delete(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<()>, ApiError> {
+    if let Ok(container) = data.inner().notifications.get_by_id(user.userid()) {
+        for id in container.update(|c| c.remove_seen()) {
+            data.inner().hub.publish(user.userid(), deleted_event(id));
         }
-        Err(_) => {
-            data.inner()
-                .notifications
-                .add_to_storage(NotificationContainer::new(user.userid().to_string()))
-                .unwrap();
-            Ok(StatusOk(()))
+    }
+    Ok(StatusOk(()))
+}
+
+/**
+ * Notification event stream (Server-Sent Events)
+ * Events: notification (with the notification ID as event ID),
+ * seen, deleted and unread (count). On reconnect the browser
+ * sends Last-Event-ID, and we send the missed notifications first.
+ * The stream is closed after STREAM_LIFETIME_SECONDS, at the
+ * access token expiration, or when its session is revoked.
+ * If every stream slot is taken, only the missed notifications
+ * are sent, and the browser reconnects later.
+ */
+#[get("/notification/stream")]
+pub fn notification_stream(
+    user: StreamLogin,
+    last_event_id: LastEventId,
+    data: State<DataLoad>,
+) -> EventStream {
+    let userid = user.0.userid();
+    // Subscribe first, so we do not miss anything
+    // between the replay and the live events.
+    let slot = data.inner().hub.take_slot();
+    let receiver = slot.as_ref().map(|_| data.inner().hub.subscribe(userid));
+    let mut initial: Vec<Event> = Vec::new();
+    let mut unread = 0;
+    if let Ok(container) = data.inner().notifications.get_by_id(userid) {
+        if let Some(last_id) = last_event_id.0 {
+            initial = container.get(|c| {
+                c.get_notifications()
+                    .iter()
+                    .filter(|n| n.get_id() > last_id)
+                    .map(|n| notification_event(n))
+                    .collect()
+            });
+        }
+        unread = container.get(|c| c.get_unread_count());
+    }
+    initial.push(unread_event(unread));
+    match (slot, receiver) {
+        (Some(slot), Some(receiver)) => {
+            let token_lifetime = (user.0.expires_at() - Utc::now()).num_seconds().max(0) as u64;
+            let closes_at = Instant::now()
+                + Duration::from_secs(std::cmp::min(STREAM_LIFETIME_SECONDS, token_lifetime));
+            let sessions = data.inner().sessions.clone();
+            let token_id = user.0.token_id().to_string();
+            let is_alive = move || match sessions.get_by_id(&token_id) {
+                Ok(session) => session.get(|s| s.is_active_at(Utc::now())),
+                Err(_) => false,
+            };
+            EventStream::new(slot, receiver, initial, closes_at, Box::new(is_alive))
+        }
+        _ => EventStream::busy(initial),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BroadcastForm {
+    message: String,
+    /**
+     * Optional link, used as raw location
+     * Relative path or http(s) URL
+     */
+    link: Option<String>,
+    /**
+     * Optional entity target, e.g. a customer or an order
+     * Cannot be used together with link
+     */
+    #[serde(default)]
+    target: Option<Location>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BroadcastResponse {
+    recipients: usize,
+}
+
+/**
+ * Send a notification to every active user
+ */
+#[post("/notification/broadcast", data = "<form>")]
+pub fn notification_broadcast_post(
+    user: Login,
+    data: State<DataLoad>,
+    form: Json<BroadcastForm>,
+) -> Result<StatusOk<BroadcastResponse>, ApiError> {
+    user.require(Permission::NotificationBroadcast)?;
+    let message = form.message.trim().to_string();
+    if message.is_empty() {
+        return Err(ApiError::BadRequest("Az üzenet nem lehet üres!".to_owned()));
+    }
+    let location = match (&form.target, &form.link) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::BadRequest(
+                "Link és cél egyszerre nem adható meg!".to_owned(),
+            ))
+        }
+        (Some(target), None) => target.clone(),
+        (None, Some(link)) => Location::Raw(link.trim().to_string()),
+        (None, None) => Location::None,
+    };
+    location.validate()?;
+    let notification = Notification::from_kind(
+        NotificationKind::Broadcast {
+            actor: user.actor(),
+            message,
+        },
+        location,
+    );
+    let recipients = data
+        .inner()
+        .users
+        .into_iter()
+        .filter(|u| u.get(|u| !u.is_disabled()))
+        .map(|u| u.get(|u| u.get_user_id().to_string()))
+        .collect::<Vec<String>>();
+    for userid in &recipients {
+        if let Err(err) = notify::notify(data.inner(), userid, notification.clone()) {
+            eprintln!("Broadcast notification error: {:?}", err);
         }
     }
+    Ok(StatusOk(BroadcastResponse {
+        recipients: recipients.len(),
+    }))
 }
 
 #[delete("/notification/<id>")]
@@ -95,6 +288,8 @@ pub fn notification_delete(
     match data.inner().notifications.get_by_id(user.userid()) {
         Ok(container) => {
             container.update(|c| c.remove_by_id(id))?;
+            data.inner().hub.publish(user.userid(), deleted_event(id));
+            publish_unread(data.inner(), user.userid());
             Ok(StatusOk(()))
         }
         Err(_) => Err(ApiError::BadRequest(
@@ -117,6 +312,8 @@ pub fn notification_seen(
                     notification.set_seen();
                 }
             });
+            data.inner().hub.publish(user.userid(), seen_event(id));
+            publish_unread(data.inner(), user.userid());
             Ok(StatusOk(()))
         }
         Err(_) => Err(ApiError::BadRequest(
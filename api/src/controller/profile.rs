@@ -21,6 +21,7 @@ use crate::DataLoad;
 use chrono::prelude::*;
 use core_lib::model::*;
 use core_lib::prelude::AppResult;
+use core_lib::totp;
 use core_lib::user;
 use rocket::State;
 use rocket_contrib::json::Json;
@@ -44,6 +45,12 @@ pub struct Profile {
     //      ||
     //      \/
     customers: Vec<String>,
+    // Only READONLY as well
+    #[serde(default)]
+    roles: Vec<Role>,
+    // Only READONLY as well
+    #[serde(default)]
+    two_factor: bool,
 }
 
 impl From<&User> for Profile {
@@ -56,6 +63,8 @@ impl From<&User> for Profile {
             date_created: user.get_date_created(),
             created_by: user.get_created_by().to_string(),
             customers: user.get_customers(),
+            roles: user.get_roles(),
+            two_factor: user.has_two_factor(),
         }
     }
 }
@@ -130,3 +139,132 @@ pub fn password_change(
     }
     Ok(StatusOk(()))
 }
+
+#[derive(Serialize, Debug)]
+pub struct TwoFactorEnrollment {
+    secret: String,
+    otpauth_uri: String,
+}
+
+/**
+ * Start two-factor enrollment
+ * Returns the secret and the otpauth:// URI for
+ * the authenticator app. 2FA is enabled only after
+ * the first code is confirmed.
+ */
+#[post("/profile/two_factor/enroll")]
+pub fn two_factor_enroll(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<TwoFactorEnrollment>, ApiError> {
+    match user::get_user_by_id(&data.inner().users, &user.userid()) {
+        Ok(usr) => {
+            let secret = usr.update(|u| u.enroll_two_factor())?;
+            Ok(StatusOk(TwoFactorEnrollment {
+                otpauth_uri: totp::otpauth_uri("GNStore", user.userid(), &secret),
+                secret,
+            }))
+        }
+        Err(_) => Err(ApiError::InternalError("Azonosítási hiba".to_owned())),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TwoFactorCode {
+    code: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+/**
+ * Confirm two-factor enrollment with the first code
+ * Returns the recovery codes, this is the only time
+ * they are shown.
+ */
+#[post("/profile/two_factor/confirm", data = "<form>")]
+pub fn two_factor_confirm(
+    user: Login,
+    data: State<DataLoad>,
+    form: Json<TwoFactorCode>,
+) -> Result<StatusOk<RecoveryCodes>, ApiError> {
+    let code = match &form.code {
+        Some(code) => code,
+        None => return Err(ApiError::BadRequest("Hiányzó ellenőrző kód".to_owned())),
+    };
+    match user::get_user_by_id(&data.inner().users, &user.userid()) {
+        Ok(usr) => Ok(StatusOk(RecoveryCodes {
+            recovery_codes: usr.update(|u| u.confirm_two_factor(code, Utc::now()))?,
+        })),
+        Err(_) => Err(ApiError::InternalError("Azonosítási hiba".to_owned())),
+    }
+}
+
+#[post("/profile/two_factor/disable", data = "<form>")]
+pub fn two_factor_disable(
+    user: Login,
+    data: State<DataLoad>,
+    form: Json<TwoFactorCode>,
+) -> Result<StatusOk<()>, ApiError> {
+    let code = match &form.code {
+        Some(code) => code,
+        None => return Err(ApiError::BadRequest("Hiányzó ellenőrző kód".to_owned())),
+    };
+    match user::get_user_by_id(&data.inner().users, &user.userid()) {
+        Ok(usr) => usr.update(|u| u.disable_two_factor(code, Utc::now()))?,
+        Err(_) => return Err(ApiError::InternalError("Azonosítási hiba".to_owned())),
+    }
+    Ok(StatusOk(()))
+}
+
+#[get("/profile/notification_preferences")]
+pub fn notification_preferences_get(
+    user: Login,
+    data: State<DataLoad>,
+) -> Result<StatusOk<NotificationPreferences>, ApiError> {
+    match user::get_user_by_id(&data.inner().users, &user.userid()) {
+        Ok(usr) => Ok(StatusOk(
+            usr.get(|u| u.get_notification_preferences().clone()),
+        )),
+        Err(_) => Err(ApiError::InternalError("Azonosítási hiba".to_owned())),
+    }
+}
+
+fn start_digest(data: &DataLoad, userid: &str) -> Result<(), ApiError> {
+    match data.notifications.get_by_id(userid) {
+        Ok(container) => container.update(|c| c.start_digest(Utc::now())),
+        Err(_) => {
+            let mut container = NotificationContainer::new(userid.to_string());
+            container.start_digest(Utc::now());
+            data.notifications.add_to_storage(container)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Set notification delivery mode per category
+ * in_app, immediate or daily_digest
+ */
+#[put("/profile/notification_preferences", data = "<form>")]
+pub fn notification_preferences_put(
+    user: Login,
+    data: State<DataLoad>,
+    form: Json<NotificationPreferences>,
+) -> Result<StatusOk<NotificationPreferences>, ApiError> {
+    match user::get_user_by_id(&data.inner().users, &user.userid()) {
+        Ok(usr) => {
+            let had_digest = usr.get(|u| u.get_notification_preferences().has_digest());
+            usr.update(|u| u.set_notification_preferences(form.clone()));
+            // First digest comes at the next digest hour,
+            // and contains notifications only from now.
+            if !had_digest && form.has_digest() {
+                start_digest(data.inner(), user.userid())?;
+            }
+            Ok(StatusOk(form.clone()))
+        }
+        Err(_) => Err(ApiError::InternalError("Azonosítási hiba".to_owned())),
+    }
+}
@@ -16,15 +16,24 @@
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::guard::Login;
+use crate::outbox;
 use crate::prelude::*;
 use crate::DataLoad;
 use chrono::prelude::*;
+use chrono::Duration;
 use core_lib::model::*;
+use core_lib::template::Template;
 use rocket::State;
 use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
+use std::env;
 use storaget::*;
 
+// Password setup link lifetime in the welcome email
+const WELCOME_TOKEN_HOURS: i64 = 48;
+// Max number of user search results
+const USER_SEARCH_LIMIT: usize = 10;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Profile {
     username: String,
@@ -42,6 +51,10 @@ pub struct Profile {
     //      ||
     //      \/
     customers: Vec<String>,
+    roles: Vec<Role>,
+    is_disabled: bool,
+    failed_login_count: u32,
+    login_locked_until: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -50,6 +63,20 @@ pub struct ProfileNew {
     email: String,
     name: String,
     phone: String,
+    // Default roles are used if None
+    roles: Option<Vec<Role>>,
+}
+
+/// Public user data for mention autocompletion
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserShort {
+    username: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RolesForm {
+    roles: Vec<Role>,
 }
 
 impl From<&User> for Profile {
@@ -62,15 +89,20 @@ impl From<&User> for Profile {
             date_created: user.get_date_created(),
             created_by: user.get_created_by().to_string(),
             customers: user.get_customers(),
+            roles: user.get_roles(),
+            is_disabled: user.is_disabled(),
+            failed_login_count: user.get_login_attempts().get_failed_count(),
+            login_locked_until: user.get_login_attempts().get_locked_until(),
         }
     }
 }
 
 #[get("/user/all")]
 pub fn user_all_get(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
 ) -> Result<StatusOk<Vec<Profile>>, ApiError> {
+    user.require(Permission::UserRead)?;
     let res = data
         .inner()
         .users
@@ -80,39 +112,153 @@ pub fn user_all_get(
     Ok(StatusOk(res))
 }
 
+/// Search active users by ID or name
+/// Used for @mention autocompletion, so it requires
+/// only issue write permission.
+#[get("/user/search?<q>")]
+pub fn user_search_get(
+    user: Login,
+    data: State<DataLoad>,
+    q: String,
+) -> Result<StatusOk<Vec<UserShort>>, ApiError> {
+    user.require(Permission::IssueWrite)?;
+    let query = q.trim().trim_start_matches('@').to_lowercase();
+    let mut res = data
+        .inner()
+        .users
+        .into_iter()
+        .map(|u| u.clone_data())
+        .filter(|u| !u.is_disabled())
+        .filter(|u| {
+            u.get_user_id().contains(&query) || u.get_user_name().to_lowercase().contains(&query)
+        })
+        .map(|u| UserShort {
+            username: u.get_user_id().to_string(),
+            name: u.get_user_name().to_string(),
+        })
+        .collect::<Vec<UserShort>>();
+    // User IDs starting with the query come first
+    res.sort_by_key(|u| (!u.username.starts_with(&query), u.username.clone()));
+    res.truncate(USER_SEARCH_LIMIT);
+    Ok(StatusOk(res))
+}
+
 #[post("/user/new", data = "<form>")]
 pub fn user_new_post(
     user: Login,
     data: State<DataLoad>,
     form: Json<ProfileNew>,
 ) -> Result<StatusOk<Profile>, ApiError> {
-    let new_user: User = User::new(
+    user.require(Permission::UserWrite)?;
+    let mut new_user: User = User::new(
         form.username.clone(),
         form.name.clone(),
         form.email.clone(),
         form.phone.clone(),
         user.userid().to_string(),
     )?;
+    if let Some(roles) = &form.roles {
+        user.require(Permission::UserRoleWrite)?;
+        new_user.set_roles(roles.clone());
+    }
     // Check if user exist;
     if let Ok(_) = data.inner().users.get_by_id(&new_user.get_id()) {
         return Err(ApiError::BadRequest(
             "A kért user ID már foglalt!".to_owned(),
         ));
     };
+    // New user has no password yet, the welcome email
+    // contains a link to set it.
+    let token = new_user.request_password_reset(Duration::hours(WELCOME_TOKEN_HOURS))?;
     match data.inner().users.add_to_storage(new_user.clone()) {
-        Ok(_) => return Ok(StatusOk((&new_user).into())),
-        Err(err) => return Err(err.into()),
+        Ok(_) => {
+            if let Err(err) = send_welcome_email(data.inner(), &new_user, &token) {
+                eprintln!("Welcome email error: {:?}", err);
+            }
+            Ok(StatusOk((&new_user).into()))
+        }
+        Err(err) => Err(err.into()),
     }
 }
 
+fn send_welcome_email(data: &DataLoad, user: &User, token: &str) -> Result<(), ApiError> {
+    let link = format!(
+        "{}/password_reset?token={}",
+        env::var("CLIENT_URL").map_err(core_lib::Error::from)?,
+        token
+    );
+    outbox::enqueue_template(
+        &data.emails,
+        user.get_user_email(),
+        Template::Welcome,
+        &[
+            ("name", user.get_user_name()),
+            ("username", user.get_user_id()),
+            ("hours", &WELCOME_TOKEN_HOURS.to_string()),
+            ("link", &link),
+        ],
+    )
+}
+
 #[get("/user/<id>")]
 pub fn user_id_get(
-    _user: Login,
+    user: Login,
     data: State<DataLoad>,
     id: String,
 ) -> Result<StatusOk<Profile>, ApiError> {
+    user.require(Permission::UserRead)?;
     match data.inner().users.get_by_id(&id) {
         Ok(user) => Ok(StatusOk((&user.clone_data()).into())),
         Err(_) => Err(ApiError::NotFound),
     }
 }
+
+#[put("/user/<id>/roles", data = "<form>")]
+pub fn user_id_roles_put(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+    form: Json<RolesForm>,
+) -> Result<StatusOk<Profile>, ApiError> {
+    user.require(Permission::UserRoleWrite)?;
+    // Admin cannot remove its own admin role,
+    // otherwise we could end up without any admin.
+    if id == user.userid() && !form.roles.contains(&Role::Admin) {
+        return Err(ApiError::BadRequest(
+            "Saját magától nem veheti el az admin jogosultságot!".to_owned(),
+        ));
+    }
+    match data.inner().users.get_by_id(&id) {
+        Ok(u) => {
+            let result = u.update(|u| -> User {
+                u.set_roles(form.roles.clone());
+                u.clone()
+            });
+            Ok(StatusOk((&result).into()))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
+
+/**
+ * Unlock login of a user
+ * and reset its failed login counter
+ */
+#[post("/user/<id>/unlock")]
+pub fn user_id_unlock_post(
+    user: Login,
+    data: State<DataLoad>,
+    id: String,
+) -> Result<StatusOk<Profile>, ApiError> {
+    user.require(Permission::UserWrite)?;
+    match data.inner().users.get_by_id(&id) {
+        Ok(u) => {
+            let result = u.update(|u| -> User {
+                u.reset_failed_logins();
+                u.clone()
+            });
+            Ok(StatusOk((&result).into()))
+        }
+        Err(_) => Err(ApiError::NotFound),
+    }
+}
@@ -15,57 +15,194 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::login::verify_token;
+use crate::login::{verify_token, TokenConfig, TokenError};
+use crate::prelude::ApiError;
 use crate::DataLoad;
+use chrono::prelude::*;
+use core_lib::login::validate_access_token;
+use core_lib::model::{Actor, Permission, Role};
 use core_lib::user;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest, Request};
 use rocket::Outcome;
 use rocket::State;
+use std::net::IpAddr;
 
 pub struct Login {
     userid: String,
     name: String,
     email: String,
+    token_id: String,
+    roles: Vec<Role>,
+    /// Expiration of the used access token
+    expires_at: DateTime<Utc>,
 }
 
 impl Login {
     pub fn userid(&self) -> &str {
         &self.userid
     }
+    /// Session ID of the used access token
+    pub fn token_id(&self) -> &str {
+        &self.token_id
+    }
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
     pub fn name(&self) -> &str {
         &self.name
     }
     pub fn email(&self) -> &str {
         &self.email
     }
+    pub fn roles(&self) -> &Vec<Role> {
+        &self.roles
+    }
+    /// Logged in user as notification actor
+    pub fn actor(&self) -> Actor {
+        Actor::new(&self.userid, &self.name)
+    }
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        permission.is_granted_by(&self.roles)
+    }
+    /// Use it at the beginning of a route
+    /// to check the required permission
+    pub fn require(&self, permission: Permission) -> Result<(), ApiError> {
+        if self.has_permission(permission) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden)
+        }
+    }
+}
+
+/// Reason of a failed login guard
+/// The 401 catcher has no access to the guard error,
+/// so we store it in the request local cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginError {
+    MissingToken,
+    InvalidToken,
+    ExpiredToken,
+    RevokedSession,
+    UnknownUser,
+}
+
+pub struct LoginFailure(pub Option<LoginError>);
+
+fn fail(request: &Request, error: LoginError) -> request::Outcome<Login, LoginError> {
+    let _ = request.local_cache(|| LoginFailure(Some(error)));
+    Outcome::Failure((Status::Unauthorized, error))
 }
 
-// TODO: Rewrite to JWT like, using Yew we do not have access to Cookies.
 impl<'a, 'r> FromRequest<'a, 'r> for Login {
-    type Error = ();
+    type Error = LoginError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Login, LoginError> {
+        match request.headers().get_one("Token") {
+            Some(token) => authenticate(request, token),
+            None => fail(request, LoginError::MissingToken),
+        }
+    }
+}
+
+/// Login for event streams
+/// Browser EventSource cannot set headers, so besides
+/// the Token header we accept the token as ?token= query
+/// parameter. Use it only for stream routes, as URLs
+/// can end up in logs.
+pub struct StreamLogin(pub Login);
 
-    fn from_request(request: &'a Request<'r>) -> request::Outcome<Login, ()> {
-        let data = request.guard::<State<DataLoad>>()?;
-        let userid: String = match &request.headers().get_one("Token") {
-            Some(token) => match verify_token(token) {
-                Ok(userid) => userid,
-                Err(_) => return Outcome::Failure((Status::Unauthorized, ())),
+impl<'a, 'r> FromRequest<'a, 'r> for StreamLogin {
+    type Error = LoginError;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<StreamLogin, LoginError> {
+        let token = match request.headers().get_one("Token") {
+            Some(token) => token.to_string(),
+            None => match request.get_query_value::<String>("token") {
+                Some(Ok(token)) => token,
+                _ => return fail(request, LoginError::MissingToken).map(StreamLogin),
             },
-            None => {
-                return Outcome::Failure((Status::Unauthorized, ()));
-            }
         };
-        match user::get_user_by_id(&data.inner().users, &userid) {
-            Ok(user) => {
-                let login = Login {
-                    userid: userid,
-                    name: user.get(|u| u.get_user_name().into()),
-                    email: user.get(|u| u.get_user_email().into()),
-                };
-                Outcome::Success(login)
-            }
-            Err(_) => Outcome::Failure((Status::Unauthorized, ())),
+        authenticate(request, &token).map(StreamLogin)
+    }
+}
+
+/// Authenticate an access token
+fn authenticate(request: &Request, token: &str) -> request::Outcome<Login, LoginError> {
+    let data = match request.guard::<State<DataLoad>>() {
+        Outcome::Success(data) => data,
+        _ => return fail(request, LoginError::UnknownUser),
+    };
+    let config = match request.guard::<State<TokenConfig>>() {
+        Outcome::Success(config) => config,
+        _ => return fail(request, LoginError::InvalidToken),
+    };
+    let claims = match verify_token(config.inner(), token) {
+        Ok(claims) => claims,
+        Err(TokenError::Expired) => return fail(request, LoginError::ExpiredToken),
+        Err(TokenError::Invalid) => return fail(request, LoginError::InvalidToken),
+    };
+    // Check the session registry
+    // Token is valid only while its session is active
+    let userid: String = match data.inner().sessions.get_by_id(claims.get_token_id()) {
+        Ok(session) => match session.get(|s| validate_access_token(s)) {
+            Ok(userid) => userid,
+            Err(_) => return fail(request, LoginError::RevokedSession),
+        },
+        Err(_) => return fail(request, LoginError::RevokedSession),
+    };
+    if userid != claims.get_userid() {
+        return fail(request, LoginError::InvalidToken);
+    }
+    match user::get_user_by_id(&data.inner().users, &userid) {
+        Ok(user) if user.get(|u| u.is_disabled()) => fail(request, LoginError::UnknownUser),
+        Ok(user) => {
+            let login = Login {
+                userid: userid,
+                name: user.get(|u| u.get_user_name().into()),
+                email: user.get(|u| u.get_user_email().into()),
+                token_id: claims.get_token_id().to_string(),
+                roles: user.get(|u| u.get_roles()),
+                expires_at: Utc.timestamp(claims.get_expires_at(), 0),
+            };
+            Outcome::Success(login)
+        }
+        Err(_) => fail(request, LoginError::UnknownUser),
+    }
+}
+
+/// Last-Event-ID of a reconnecting event stream
+/// The browser sends it automatically on reconnect.
+pub struct LastEventId(pub Option<usize>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for LastEventId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<LastEventId, ()> {
+        let id = request
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.trim().parse::<usize>().ok());
+        Outcome::Success(LastEventId(id))
+    }
+}
+
+/// Client IP address
+/// Uses the X-Real-IP header if it is set by the proxy,
+/// otherwise the remote address of the connection.
+pub struct ClientIp(pub IpAddr);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ClientIp, ()> {
+        match request.client_ip() {
+            Some(ip) => Outcome::Success(ClientIp(ip)),
+            None => Outcome::Failure((Status::BadRequest, ())),
         }
     }
 }
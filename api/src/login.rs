@@ -15,29 +15,166 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+use chrono::prelude::*;
+use chrono::Duration;
+use core_lib::config::number_from_env;
 use core_lib::error::Error;
+use core_lib::model::Session;
 use core_lib::prelude::AppResult;
+use core_lib::throttle::{LoginAttempts, ThrottlePolicy};
 use crypto::sha2::Sha256;
 use jwt::{Header, Token};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::default::Default;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use storaget::*;
 
+// Default access token lifetime in seconds (15 minutes)
+const DEFAULT_EXPIRES_IN: i64 = 15 * 60;
+// Default refresh token lifetime in seconds (30 days)
+const DEFAULT_REFRESH_EXPIRES_IN: i64 = 30 * 24 * 60 * 60;
+// Minimum accepted length of the signing secret
+const MIN_SECRET_LEN: usize = 32;
+// Two-factor challenge token lifetime in seconds (5 minutes)
+const CHALLENGE_EXPIRES_IN: i64 = 5 * 60;
+// Token scopes
+const SCOPE_ACCESS: &str = "access";
+const SCOPE_TWO_FACTOR: &str = "2fa";
+
+/// JWT claims
+/// We use the standard registered claim names,
+/// so any JWT tool can inspect a token.
 #[derive(Default, Deserialize, Serialize, RustcDecodable, RustcEncodable)]
-struct Custom {
+pub struct Claims {
+    /// Userid the token belongs to
     uid: String,
-    rhino: bool,
+    /// Issued at, UNIX timestamp
+    iat: i64,
+    /// Expires at, UNIX timestamp
+    exp: i64,
+    /// Unique token ID
+    jti: String,
+    /// What the token can be used for,
+    /// access or two-factor challenge
+    scope: String,
+}
+
+impl Claims {
+    pub fn get_userid(&self) -> &str {
+        &self.uid
+    }
+    pub fn get_token_id(&self) -> &str {
+        &self.jti
+    }
+    pub fn get_issued_at(&self) -> i64 {
+        self.iat
+    }
+    pub fn get_expires_at(&self) -> i64 {
+        self.exp
+    }
+}
+
+/// Token signing configuration
+/// Loaded once at startup and managed by Rocket.
+pub struct TokenConfig {
+    secret: Vec<u8>,
+    expires_in: i64,
+    refresh_expires_in: i64,
+}
+
+impl TokenConfig {
+    pub fn new(secret: &str, expires_in: i64, refresh_expires_in: i64) -> AppResult<Self> {
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(Error::InternalError(format!(
+                "JWT secret must be at least {} characters long",
+                MIN_SECRET_LEN
+            )));
+        }
+        if expires_in <= 0 || refresh_expires_in <= 0 {
+            return Err(Error::InternalError(
+                "JWT expiry must be a positive number of seconds".to_owned(),
+            ));
+        }
+        Ok(TokenConfig {
+            secret: secret.as_bytes().to_vec(),
+            expires_in,
+            refresh_expires_in,
+        })
+    }
+    /// Load config from ENV
+    /// JWT_SECRET is required, JWT_EXPIRES_IN and
+    /// JWT_REFRESH_EXPIRES_IN (seconds) are optional.
+    pub fn from_env() -> AppResult<Self> {
+        let secret = env::var("JWT_SECRET")?;
+        let expires_in = number_from_env("JWT_EXPIRES_IN", DEFAULT_EXPIRES_IN, 1..)?;
+        let refresh_expires_in =
+            number_from_env("JWT_REFRESH_EXPIRES_IN", DEFAULT_REFRESH_EXPIRES_IN, 1..)?;
+        TokenConfig::new(&secret, expires_in, refresh_expires_in)
+    }
+    /// Access token lifetime
+    pub fn get_expires_in(&self) -> Duration {
+        Duration::seconds(self.expires_in)
+    }
+    /// Session (refresh token family) lifetime
+    pub fn get_refresh_expires_in(&self) -> Duration {
+        Duration::seconds(self.refresh_expires_in)
+    }
+}
+
+/// Token verification failure
+/// We need to tell the client whether it
+/// should log in again (expired) or the token
+/// is not ours at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenError {
+    Invalid,
+    Expired,
+}
+
+/// Create a short-lived signed access token for a session
+/// The token ID is the session ID, so we can
+/// look it up in the session registry later.
+pub fn create_token(config: &TokenConfig, session: &Session) -> AppResult<String> {
+    let now = Utc::now();
+    let expires_at = std::cmp::min(now + config.get_expires_in(), session.get_expires_at());
+    sign(
+        config,
+        Claims {
+            uid: session.get_userid().into(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            jti: session.get_id().into(),
+            scope: SCOPE_ACCESS.into(),
+        },
+    )
 }
 
-pub fn create_token(user_id: &str) -> AppResult<String> {
+/// Create a two-factor challenge token
+/// Issued after a valid password when the user has 2FA
+/// enabled. It cannot be used as access token, only to
+/// complete the login with a second factor code.
+pub fn create_challenge_token(config: &TokenConfig, userid: &str) -> AppResult<String> {
+    let now = Utc::now();
+    sign(
+        config,
+        Claims {
+            uid: userid.into(),
+            iat: now.timestamp(),
+            exp: (now + Duration::seconds(CHALLENGE_EXPIRES_IN)).timestamp(),
+            jti: core_lib::login::generate_token_id(),
+            scope: SCOPE_TWO_FACTOR.into(),
+        },
+    )
+}
+
+fn sign(config: &TokenConfig, claims: Claims) -> AppResult<String> {
     let header: Header = Default::default();
-    let claims = Custom {
-        uid: user_id.into(),
-        rhino: true,
-        ..Default::default()
-    };
     let token = Token::new(header, claims);
 
-    match token.signed(b"secret_key", Sha256::new()) {
+    match token.signed(&config.secret, Sha256::new()) {
         Ok(token) => return Ok(token),
         Err(_) => {
             return Err(Error::InternalError(
@@ -47,21 +184,66 @@ pub fn create_token(user_id: &str) -> AppResult<String> {
     }
 }
 
-pub fn verify_token(token: &str) -> AppResult<String> {
-    let token = match Token::<Header, Custom>::parse(token) {
+/// Verify access token
+pub fn verify_token(config: &TokenConfig, token: &str) -> Result<Claims, TokenError> {
+    verify_scope(config, token, SCOPE_ACCESS)
+}
+
+/// Verify two-factor challenge token
+pub fn verify_challenge_token(config: &TokenConfig, token: &str) -> Result<Claims, TokenError> {
+    verify_scope(config, token, SCOPE_TWO_FACTOR)
+}
+
+fn verify_scope(config: &TokenConfig, token: &str, scope: &str) -> Result<Claims, TokenError> {
+    let token = match Token::<Header, Claims>::parse(token) {
         Ok(v) => v,
-        Err(_) => {
-            return Err(Error::InternalError(
-                "Hibás authentikációs TOKEN!".to_owned(),
-            ))
-        }
+        Err(_) => return Err(TokenError::Invalid),
     };
+    // First check the signature, so we never tell
+    // a forged token that it is "only" expired.
+    if !token.verify(&config.secret, Sha256::new()) {
+        return Err(TokenError::Invalid);
+    }
+    if token.claims.scope != scope {
+        return Err(TokenError::Invalid);
+    }
+    if token.claims.exp <= Utc::now().timestamp() {
+        return Err(TokenError::Expired);
+    }
+    Ok(token.claims)
+}
+
+/// Failed login attempts per client IP
+/// Kept in memory only, a restart resets it.
+/// Expired entries are dropped on every check,
+/// so the map holds only the recently failing IPs.
+pub struct IpThrottle(Mutex<HashMap<IpAddr, LoginAttempts>>);
 
-    if token.verify(b"secret_key", Sha256::new()) {
-        Ok(token.claims.uid)
-    } else {
-        Err(Error::InternalError(
-            "Hibás authentikációs TOKEN!".to_owned(),
-        ))
+impl IpThrottle {
+    pub fn new() -> Self {
+        IpThrottle(Mutex::new(HashMap::new()))
+    }
+    fn sweep(attempts: &mut HashMap<IpAddr, LoginAttempts>, now: DateTime<Utc>) {
+        let policy = ThrottlePolicy::ip();
+        attempts.retain(|_, a| !a.is_expired(&policy, now));
+    }
+    pub fn is_locked(&self, ip: IpAddr, now: DateTime<Utc>) -> bool {
+        let mut attempts = self.0.lock().unwrap();
+        IpThrottle::sweep(&mut attempts, now);
+        match attempts.get(&ip) {
+            Some(attempts) => attempts.is_locked(now),
+            None => false,
+        }
+    }
+    pub fn register_failure(&self, ip: IpAddr, now: DateTime<Utc>) {
+        let mut attempts = self.0.lock().unwrap();
+        IpThrottle::sweep(&mut attempts, now);
+        attempts
+            .entry(ip)
+            .or_insert(LoginAttempts::new())
+            .register_failure(&ThrottlePolicy::ip(), now);
+    }
+    pub fn reset(&self, ip: IpAddr) {
+        self.0.lock().unwrap().remove(&ip);
     }
 }
@@ -32,21 +32,29 @@ extern crate serde;
 extern crate serde_derive;
 extern crate storaget;
 
+pub mod bootstrap;
 pub mod controller;
 pub mod cors;
 pub mod guard;
 pub mod login;
+pub mod notify;
+pub mod outbox;
 pub mod prelude;
+pub mod stream;
 
 use crate::prelude::*;
+use bootstrap::{bootstrap_admin, bootstrap_issue_numbers, bootstrap_labels, SetupToken};
 use core_lib::model::*;
 use guard::*;
+use login::{IpThrottle, TokenConfig};
 use rocket::response::NamedFile;
 use rocket::Request;
 use rocket_cors::AllowedHeaders;
 use serde::Serialize;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use storaget::*;
+use stream::EventHub;
 
 #[get("/")]
 fn index() -> String {
@@ -76,8 +84,13 @@ fn not_found(_: &Request<'_>) -> ApiError {
 }
 
 #[catch(401)]
-fn unauthorized(_: &Request<'_>) -> ApiError {
-    ApiError::Unauthorized
+fn unauthorized(req: &Request<'_>) -> ApiError {
+    match req.local_cache(|| LoginFailure(None)).0 {
+        Some(LoginError::ExpiredToken) => ApiError::TokenExpired,
+        Some(LoginError::InvalidToken) => ApiError::TokenInvalid,
+        Some(LoginError::RevokedSession) => ApiError::SessionRevoked,
+        _ => ApiError::Unauthorized,
+    }
 }
 
 #[catch(422)]
@@ -85,7 +98,11 @@ fn form_error(_: &Request<'_>) -> ApiError {
     ApiError::InternalError("Minden mező kitöltése kötelező!".to_owned())
 }
 
-fn rocket(data: DataLoad) -> rocket::Rocket {
+fn rocket(
+    mut data: DataLoad,
+    token_config: TokenConfig,
+    setup_token: SetupToken,
+) -> rocket::Rocket {
     let mut methods = std::collections::HashSet::new();
     methods.insert(rocket_cors::Method::from(rocket::http::Method::Post));
     methods.insert(rocket_cors::Method::from(rocket::http::Method::Get));
@@ -104,30 +121,60 @@ fn rocket(data: DataLoad) -> rocket::Rocket {
     .to_cors()
     .unwrap();
 
-    rocket::ignite()
+    let rocket = rocket::ignite();
+    // Event streams can use at most half of the workers
+    data.hub = EventHub::new(std::cmp::max(rocket.config().workers as usize / 2, 1));
+
+    rocket
         .attach(cors)
         // .attach(CORS())
         .manage(data)
+        .manage(token_config)
+        .manage(setup_token)
+        .manage(IpThrottle::new())
         .mount("/", routes![index])
         .mount(
             "/api",
             routes![
                 controller::login::post,
+                controller::login::two_factor,
+                controller::login::refresh,
+                controller::login::setup,
                 controller::login::reset_password,
+                controller::login::reset_password_confirm,
+                controller::login::logout,
+                controller::login::logout_all,
                 controller::profile::profile_get,
                 controller::profile::profile_post,
                 controller::profile::password_change,
+                controller::profile::two_factor_enroll,
+                controller::profile::two_factor_confirm,
+                controller::profile::two_factor_disable,
+                controller::profile::notification_preferences_get,
+                controller::profile::notification_preferences_put,
                 controller::notification::notification_get,
-                controller::notification::notification_new_get,
+                controller::notification::notification_unread_get,
+                controller::notification::notification_seen_all,
+                controller::notification::notification_seen_delete,
+                controller::notification::notification_stream,
+                controller::notification::notification_broadcast_post,
                 controller::notification::notification_delete,
                 controller::notification::notification_seen,
                 controller::customer::customer_all_get,
                 controller::customer::customer_new_post,
                 controller::customer::customer_id_get,
                 controller::customer::customer_id_post,
+                controller::email::email_all_get,
+                controller::email::email_failed_get,
+                controller::email::email_id_retry_post,
+                controller::email::email_template_all_get,
+                controller::email::email_template_preview_get,
                 controller::user::user_all_get,
                 controller::user::user_id_get,
+                controller::user::user_search_get,
                 controller::user::user_new_post,
+                controller::user::user_id_roles_put,
+                controller::user::user_id_unlock_post,
                 controller::issue::user_new_put,
                 controller::issue::issue_all_get,
                 controller::issue::issue_id_get,
@@ -136,30 +183,64 @@ fn rocket(data: DataLoad) -> rocket::Rocket {
                 controller::issue::issue_id_assign_to_post,
                 controller::issue::issue_id_open_post,
                 controller::issue::issue_id_close_post,
+                controller::issue::issue_id_title_put,
+                controller::issue::issue_id_description_put,
                 controller::issue::issue_id_comment_post,
+                controller::issue::issue_id_comment_put,
+                controller::issue::issue_id_comment_delete,
                 controller::issue::issue_id_comment_like_post,
                 controller::issue::issue_id_comment_dislike_post,
                 controller::issue::issue_id_label_add_post,
                 controller::issue::issue_id_label_remove_post,
+                controller::label::label_all_get,
+                controller::label::label_new_post,
+                controller::label::label_id_put,
+                controller::label::label_id_delete,
             ],
         )
         .register(catchers![not_found, unauthorized, form_error])
 }
 
 pub struct DataLoad {
-    users: Storage<User>,
-    notifications: Storage<NotificationContainer>,
+    users: Arc<Storage<User>>,
+    notifications: Arc<Storage<NotificationContainer>>,
     customers: Storage<Customer>,
     issues: Storage<Issue>,
+    counters: Storage<Counter>,
+    labels: Storage<IssueLabel>,
+    sessions: Arc<Storage<Session>>,
+    emails: Arc<Storage<OutboxEmail>>,
+    hub: EventHub,
 }
 
 fn main() -> StorageResult<()> {
     let data = DataLoad {
-        users: Storage::load_or_init::<User>("data/users")?,
-        notifications: Storage::load_or_init::<NotificationContainer>("data/notifications")?,
+        users: Arc::new(Storage::load_or_init::<User>("data/users")?),
+        notifications: Arc::new(Storage::load_or_init::<NotificationContainer>(
+            "data/notifications",
+        )?),
         customers: Storage::load_or_init::<Customer>("data/customers")?,
         issues: Storage::load_or_init::<Issue>("data/issues")?,
+        counters: Storage::load_or_init::<Counter>("data/counters")?,
+        labels: Storage::load_or_init::<IssueLabel>("data/labels")?,
+        sessions: Arc::new(Storage::load_or_init::<Session>("data/sessions")?),
+        emails: Arc::new(Storage::load_or_init::<OutboxEmail>("data/emails")?),
+        // Stream limit is set from the Rocket config, see rocket()
+        hub: EventHub::new(0),
     };
-    rocket(data).launch();
+    let token_config = TokenConfig::from_env().expect("Invalid JWT configuration");
+    let setup_token = bootstrap_admin(&data.users).expect("Admin bootstrap failed");
+    bootstrap_issue_numbers(&data.issues, &data.counters).expect("Issue numbering failed");
+    bootstrap_labels(&data.issues, &data.labels).expect("Label migration failed");
+    let transport = core_lib::email::transport_from_env().expect("Invalid mail configuration");
+    outbox::start_worker(data.emails.clone(), transport);
+    notify::start_digest_worker(
+        data.users.clone(),
+        data.notifications.clone(),
+        data.emails.clone(),
+    );
+    let retention = RetentionPolicy::from_env().expect("Invalid notification retention");
+    notify::start_retention_worker(data.notifications.clone(), retention);
+    rocket(data, token_config, setup_token).launch();
     Ok(())
 }
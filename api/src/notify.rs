@@ -0,0 +1,219 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::controller::notification::NotificationResponse;
+use crate::outbox;
+use crate::prelude::*;
+use crate::stream::Event;
+use crate::DataLoad;
+use chrono::prelude::*;
+use core_lib::model::*;
+use core_lib::notification::digest_email;
+use core_lib::prelude::AppResult;
+use core_lib::template::Template;
+use std::env;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use storaget::*;
+
+// Daily digest is sent after this hour (UTC)
+const DIGEST_HOUR: u32 = 7;
+// Seconds between two digest checks
+const DIGEST_INTERVAL_SECONDS: u64 = 10 * 60;
+// Seconds between two retention runs
+const RETENTION_INTERVAL_SECONDS: u64 = 60 * 60;
+
+pub trait NF {
+    fn notify_user(&self, userid: &str, notification: Notification) -> AppResult<()>;
+}
+
+impl NF for DataLoad {
+    /// Add notification to the user notification container,
+    /// and push it to the open event streams of the user.
+    /// If it is collapsed into an older unseen notification,
+    /// the older one is sent as deleted, as the group has a new ID.
+    fn notify_user(&self, userid: &str, notification: Notification) -> AppResult<()> {
+        let added = match self.notifications.get_by_id(userid) {
+            Ok(container) => {
+                let (id, grouped) = container.update(|c| {
+                    let grouped = c.find_group(&notification).map(|n| n.get_id());
+                    (c.add(notification.clone()), grouped)
+                });
+                if let Some(grouped) = grouped {
+                    self.hub.publish(userid, deleted_event(grouped));
+                }
+                container.get(|c| c.find_by_id(id).cloned())
+            }
+            Err(_) => {
+                let mut notification_object = NotificationContainer::new(userid.to_string());
+                let id = notification_object.add(notification.clone());
+                let added = notification_object.find_by_id(id).cloned();
+                self.notifications.add_to_storage(notification_object)?;
+                added
+            }
+        };
+        if let Some(added) = added {
+            self.hub.publish(userid, notification_event(&added));
+        }
+        publish_unread(self, userid);
+        Ok(())
+    }
+}
+
+/// Notification event
+/// Its ID is the notification ID, so a reconnecting
+/// stream can continue after the last received one.
+pub fn notification_event(notification: &Notification) -> Event {
+    Event::new(
+        "notification",
+        serde_json::to_string(&NotificationResponse::from(notification)).unwrap_or_default(),
+    )
+    .with_id(notification.get_id())
+}
+
+/// Deleted notification event
+pub fn deleted_event(id: usize) -> Event {
+    Event::new("deleted", format!("{{\"id\":{}}}", id))
+}
+
+/// Seen notification event
+pub fn seen_event(id: usize) -> Event {
+    Event::new("seen", format!("{{\"id\":{}}}", id))
+}
+
+/// Unread count event
+pub fn unread_event(count: usize) -> Event {
+    Event::new("unread", format!("{{\"unread\":{}}}", count))
+}
+
+/// Push the current unread count to the open streams of the user
+pub fn publish_unread(data: &DataLoad, userid: &str) {
+    let count = match data.notifications.get_by_id(userid) {
+        Ok(container) => container.get(|c| c.get_unread_count()),
+        Err(_) => 0,
+    };
+    data.hub.publish(userid, unread_event(count));
+}
+
+/// Does the user want an immediate email
+/// for the given notification category
+pub fn wants_email(data: &DataLoad, userid: &str, category: NotificationCategory) -> bool {
+    match data.users.get_by_id(userid) {
+        Ok(user) => user.get(|u: &User| {
+            !u.is_disabled()
+                && u.get_notification_preferences().get(category) == DeliveryMode::Immediate
+        }),
+        Err(_) => false,
+    }
+}
+
+/// Notify a user
+/// Adds the notification in-app, and emails it right away
+/// if the user asked for immediate email in its category.
+/// Daily digest categories are sent by the digest worker.
+pub fn notify(data: &DataLoad, userid: &str, notification: Notification) -> Result<(), ApiError> {
+    data.notify_user(userid, notification.clone())?;
+    if wants_email(data, userid, notification.get_category()) {
+        let user = data.users.get_by_id(userid)?.clone_data();
+        outbox::enqueue_template(
+            &data.emails,
+            user.get_user_email(),
+            Template::Notification,
+            &[
+                ("name", user.get_user_name()),
+                ("subject", notification.get_subject()),
+                ("link", &client_link(notification.get_location())?),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn client_link(location: Option<String>) -> Result<String, ApiError> {
+    Ok(format!(
+        "{}{}",
+        env::var("CLIENT_URL").map_err(core_lib::Error::from)?,
+        location.unwrap_or("/notification".into())
+    ))
+}
+
+/// Start the daily digest worker thread
+pub fn start_digest_worker(
+    users: Arc<Storage<User>>,
+    notifications: Arc<Storage<NotificationContainer>>,
+    emails: Arc<Storage<OutboxEmail>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        users
+            .into_iter()
+            .map(|u| u.clone_data())
+            .filter(|u| !u.is_disabled() && u.get_notification_preferences().has_digest())
+            .for_each(|u| {
+                if let Err(err) = send_digest(&notifications, &emails, &u, Utc::now()) {
+                    eprintln!("Notification digest error: {:?}", err);
+                }
+            });
+        thread::sleep(Duration::from_secs(DIGEST_INTERVAL_SECONDS));
+    })
+}
+
+/// Start the notification retention worker thread
+/// Prunes old seen notifications by the retention policy.
+pub fn start_retention_worker(
+    notifications: Arc<Storage<NotificationContainer>>,
+    policy: RetentionPolicy,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        notifications.into_iter().for_each(|container| {
+            // Update only when there is something to prune
+            let now = Utc::now();
+            if container.get(|c| c.needs_prune(&policy, now)) {
+                container.update(|c| c.prune(&policy, now));
+            }
+        });
+        thread::sleep(Duration::from_secs(RETENTION_INTERVAL_SECONDS));
+    })
+}
+
+fn send_digest(
+    notifications: &Storage<NotificationContainer>,
+    emails: &Storage<OutboxEmail>,
+    user: &User,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    let container = match notifications.get_by_id(user.get_user_id()) {
+        Ok(container) => container,
+        Err(_) => return Ok(()),
+    };
+    // Opted in before digest periods were started at opt in,
+    // so we start it now, without sending anything.
+    if !container.get(|c| c.has_digest_started()) {
+        container.update(|c| c.start_digest(now));
+        return Ok(());
+    }
+    if !container.get(|c| c.is_digest_due(now, DIGEST_HOUR)) {
+        return Ok(());
+    }
+    let link = client_link(None)?;
+    let message = container.get(|c| digest_email(c, user, &link, DIGEST_HOUR, now))?;
+    if let Some(message) = message {
+        outbox::enqueue(emails, message)?;
+    }
+    container.update(|c| c.set_digest_sent(now));
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::prelude::*;
+use chrono::prelude::*;
+use core_lib::email::{MailTransport, Message};
+use core_lib::model::OutboxEmail;
+use core_lib::template::Template;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use storaget::*;
+
+// Seconds between two outbox checks
+const WORKER_INTERVAL_SECONDS: u64 = 10;
+
+/// Add an email to the outbox
+/// The worker sends it in the background,
+/// so SMTP errors never fail the request.
+pub fn enqueue(emails: &Storage<OutboxEmail>, message: Message) -> Result<(), ApiError> {
+    emails.add_to_storage(OutboxEmail::new(message))?;
+    Ok(())
+}
+
+/// Render a template and add it to the outbox
+pub fn enqueue_template(
+    emails: &Storage<OutboxEmail>,
+    to: &str,
+    template: Template,
+    vars: &[(&str, &str)],
+) -> Result<(), ApiError> {
+    enqueue(emails, template.render(vars)?.into_message(to)?)
+}
+
+/// Start the outbox worker thread
+/// Sends every due email through the transport, and
+/// schedules the next attempt with backoff on failure.
+/// It also clears the content of sent and expired dead letter emails.
+pub fn start_worker(
+    emails: Arc<Storage<OutboxEmail>>,
+    transport: Arc<dyn MailTransport>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        send_due(&emails, transport.as_ref(), Utc::now());
+        redact_expired(&emails, Utc::now());
+        thread::sleep(Duration::from_secs(WORKER_INTERVAL_SECONDS));
+    })
+}
+
+/// Retention sweep
+/// Email bodies can contain single-use links,
+/// so we do not keep them once they are not needed.
+fn redact_expired(emails: &Storage<OutboxEmail>, now: DateTime<Utc>) {
+    emails
+        .into_iter()
+        .filter(|e| e.get(|e| e.needs_redact(now)))
+        .for_each(|e| e.update(|e| e.redact()));
+}
+
+fn send_due(emails: &Storage<OutboxEmail>, transport: &dyn MailTransport, now: DateTime<Utc>) {
+    emails
+        .into_iter()
+        .filter(|e| e.get(|e| e.is_due(now)))
+        .for_each(|e| {
+            // Send from a copy, so we do not hold
+            // the storage lock during the SMTP call.
+            let result = e.clone_data().send(transport);
+            let _ = e.update(|e| e.register_delivery(&result, Utc::now()));
+        });
+}
@@ -98,6 +98,11 @@ pub enum ApiError {
     InternalError(String),
     NotFound,
     Unauthorized,
+    Forbidden,
+    TooManyRequests,
+    TokenExpired,
+    TokenInvalid,
+    SessionRevoked,
 }
 
 // Rocket responder for ApiError
@@ -110,6 +115,11 @@ impl<'r> Responder<'static> for ApiError {
                 ApiError::InternalError(_) => Status::InternalServerError,
                 ApiError::NotFound => Status::NotFound,
                 ApiError::Unauthorized => Status::Unauthorized,
+                ApiError::Forbidden => Status::Forbidden,
+                ApiError::TooManyRequests => Status::TooManyRequests,
+                ApiError::TokenExpired => Status::Unauthorized,
+                ApiError::TokenInvalid => Status::Unauthorized,
+                ApiError::SessionRevoked => Status::Unauthorized,
             })
             .sized_body(Cursor::new(match self {
                 ApiError::BadRequest(message) => {
@@ -126,6 +136,26 @@ impl<'r> Responder<'static> for ApiError {
                     "Ön nincs bejelentkezve! Jelentkezzen be!".to_owned(),
                 ))
                 .unwrap(),
+                ApiError::Forbidden => serde_json::to_string(&ApiErrorScheme::new(
+                    "Nincs jogosultsága a művelethez!".to_owned(),
+                ))
+                .unwrap(),
+                ApiError::TooManyRequests => serde_json::to_string(&ApiErrorScheme::new(
+                    "Túl sok sikertelen próbálkozás! Próbálja újra később!".to_owned(),
+                ))
+                .unwrap(),
+                ApiError::TokenExpired => serde_json::to_string(&ApiErrorScheme::new(
+                    "A bejelentkezés lejárt! Jelentkezzen be újra!".to_owned(),
+                ))
+                .unwrap(),
+                ApiError::TokenInvalid => serde_json::to_string(&ApiErrorScheme::new(
+                    "Hibás authentikációs TOKEN!".to_owned(),
+                ))
+                .unwrap(),
+                ApiError::SessionRevoked => serde_json::to_string(&ApiErrorScheme::new(
+                    "A munkamenet kijelentkeztetve! Jelentkezzen be újra!".to_owned(),
+                ))
+                .unwrap(),
             }))
             .ok()
     }
@@ -136,6 +166,7 @@ impl From<core_lib::Error> for ApiError {
     fn from(err: core_lib::Error) -> Self {
         match err {
             core_lib::Error::BadRequest(msg) => ApiError::BadRequest(msg),
+            core_lib::Error::Forbidden(_) => ApiError::Forbidden,
             core_lib::Error::InternalError(msg) => ApiError::InternalError(msg),
         }
     }
@@ -0,0 +1,414 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use rocket::http::{ContentType, Status};
+use rocket::response::{Responder, Response};
+use rocket::Request;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Rocket fills a whole chunk before writing it out, and the
+// connection itself is buffered (8 KiB). So we pad every frame
+// to a full chunk, otherwise events would wait in the buffers.
+const CHUNK_SIZE: usize = 8 * 1024;
+// An open stream holds a Rocket worker thread, so we close it after
+// this long, and the client reconnects with Last-Event-ID.
+// Keep it below the proxy read timeout (nginx default is 60 s).
+pub const STREAM_LIFETIME_SECONDS: u64 = 50;
+// Interval of the session check of an open stream
+const CHECK_SECONDS: u64 = 15;
+// Client reconnection delay in milliseconds
+const RETRY_MILLISECONDS: u64 = 2000;
+// Reconnection delay when there was no free stream slot
+const BUSY_RETRY_MILLISECONDS: u64 = 30000;
+
+/// Server-Sent Event
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Event ID, the client sends back the last
+    /// one as Last-Event-ID when it reconnects
+    id: Option<usize>,
+    /// Event name
+    event: String,
+    /// JSON data
+    data: String,
+}
+
+impl Event {
+    pub fn new(event: &str, data: String) -> Self {
+        Event {
+            id: None,
+            event: event.into(),
+            data,
+        }
+    }
+    pub fn with_id(mut self, id: usize) -> Self {
+        self.id = Some(id);
+        self
+    }
+    fn to_frame(&self) -> String {
+        let mut frame = String::new();
+        if let Some(id) = self.id {
+            frame.push_str(&format!("id: {}\n", id));
+        }
+        frame.push_str(&format!("event: {}\n", self.event));
+        for line in self.data.lines() {
+            frame.push_str(&format!("data: {}\n", line));
+        }
+        frame.push('\n');
+        frame
+    }
+}
+
+/// Event hub
+/// Keeps the open streams per user,
+/// and sends them the published events.
+/// The number of open streams is limited, so they
+/// cannot take every Rocket worker thread.
+pub struct EventHub {
+    streams: Mutex<HashMap<String, Vec<Sender<Event>>>>,
+    open: Arc<AtomicUsize>,
+    max_open: usize,
+}
+
+/// Open stream slot
+/// Taken from the hub, and given back on drop.
+pub struct StreamSlot(Arc<AtomicUsize>);
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl EventHub {
+    pub fn new(max_open: usize) -> Self {
+        EventHub {
+            streams: Mutex::new(HashMap::new()),
+            open: Arc::new(AtomicUsize::new(0)),
+            max_open,
+        }
+    }
+    /// Take an open stream slot, if there is any free
+    pub fn take_slot(&self) -> Option<StreamSlot> {
+        let mut open = self.open.load(Ordering::SeqCst);
+        while open < self.max_open {
+            match self
+                .open
+                .compare_exchange(open, open + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return Some(StreamSlot(self.open.clone())),
+                Err(current) => open = current,
+            }
+        }
+        None
+    }
+    /// Open a new stream for a user
+    pub fn subscribe(&self, userid: &str) -> Receiver<Event> {
+        let (sender, receiver) = channel();
+        self.streams
+            .lock()
+            .unwrap()
+            .entry(userid.to_string())
+            .or_insert(Vec::new())
+            .push(sender);
+        receiver
+    }
+    /// Send event to every open stream of a user
+    /// Closed streams are removed.
+    pub fn publish(&self, userid: &str, event: Event) {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(senders) = streams.get_mut(userid) {
+            senders.retain(|sender| sender.send(event.clone()).is_ok());
+            if senders.is_empty() {
+                streams.remove(userid);
+            }
+        }
+    }
+}
+
+/// Frame padded with an SSE comment line to the next chunk boundary
+fn pad_frame(frame: String) -> Vec<u8> {
+    let mut buffer = frame.into_bytes();
+    let missing = CHUNK_SIZE - (buffer.len() + 2) % CHUNK_SIZE;
+    buffer.push(b':');
+    buffer.extend(std::iter::repeat(b' ').take(missing % CHUNK_SIZE));
+    buffer.push(b'\n');
+    buffer
+}
+
+/// Live part of an event stream
+struct LiveStream {
+    receiver: Receiver<Event>,
+    /// Checked periodically, the stream ends when it returns false
+    is_alive: Box<dyn Fn() -> bool + Send>,
+    check_interval: Duration,
+    closes_at: Instant,
+    _slot: StreamSlot,
+}
+
+impl LiveStream {
+    /// Wait for the next events
+    /// Returns None when the stream should end.
+    fn next_frame(&self) -> Option<String> {
+        loop {
+            let now = Instant::now();
+            if now >= self.closes_at {
+                return None;
+            }
+            let wait = std::cmp::min(self.closes_at - now, self.check_interval);
+            match self.receiver.recv_timeout(wait) {
+                Ok(event) => {
+                    // Send the queued ones in the same padded frame
+                    let mut frame = event.to_frame();
+                    while let Ok(event) = self.receiver.try_recv() {
+                        frame.push_str(&event.to_frame());
+                    }
+                    return Some(frame);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if !(self.is_alive)() {
+                        return None;
+                    }
+                }
+                // Hub is gone, end of stream
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+}
+
+/// Event stream response body
+/// Blocks a Rocket worker while the client is connected,
+/// at most until `closes_at`.
+pub struct EventStream {
+    live: Option<LiveStream>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl EventStream {
+    /// New stream, `initial` events are sent first
+    /// It ends at `closes_at`, or when `is_alive` returns false.
+    pub fn new(
+        slot: StreamSlot,
+        receiver: Receiver<Event>,
+        initial: Vec<Event>,
+        closes_at: Instant,
+        is_alive: Box<dyn Fn() -> bool + Send>,
+    ) -> Self {
+        EventStream {
+            live: Some(LiveStream {
+                receiver,
+                is_alive,
+                check_interval: Duration::from_secs(CHECK_SECONDS),
+                closes_at,
+                _slot: slot,
+            }),
+            buffer: initial_frame(RETRY_MILLISECONDS, initial),
+            position: 0,
+        }
+    }
+    /// Stream without a free slot
+    /// Sends the initial events and ends, the client
+    /// reconnects later with Last-Event-ID.
+    pub fn busy(initial: Vec<Event>) -> Self {
+        EventStream {
+            live: None,
+            buffer: initial_frame(BUSY_RETRY_MILLISECONDS, initial),
+            position: 0,
+        }
+    }
+}
+
+fn initial_frame(retry: u64, initial: Vec<Event>) -> Vec<u8> {
+    let mut frame = format!("retry: {}\n\n", retry);
+    for event in initial {
+        frame.push_str(&event.to_frame());
+    }
+    pad_frame(frame)
+}
+
+impl Read for EventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.buffer.len() {
+            let frame = match &self.live {
+                Some(live) => live.next_frame(),
+                None => None,
+            };
+            match frame {
+                Some(frame) => {
+                    self.buffer = pad_frame(frame);
+                    self.position = 0;
+                }
+                None => {
+                    // Give back the slot right away
+                    self.live = None;
+                    return Ok(0);
+                }
+            }
+        }
+        let len = std::cmp::min(buf.len(), self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl<'r> Responder<'r> for EventStream {
+    fn respond_to(self, _: &Request) -> Result<Response<'r>, Status> {
+        Response::build()
+            .header(ContentType::new("text", "event-stream"))
+            .raw_header("Cache-Control", "no-cache")
+            // Tell nginx not to buffer the stream
+            .raw_header("X-Accel-Buffering", "no")
+            .chunked_body(self, CHUNK_SIZE as u64)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(stream: &mut EventStream) -> String {
+        let mut result = String::new();
+        stream.read_to_string(&mut result).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_to_frame() {
+        let event = Event::new("unread", "{\"unread\":3}".into());
+        assert_eq!(event.to_frame(), "event: unread\ndata: {\"unread\":3}\n\n");
+        let event = Event::new("notification", "first\nsecond".into()).with_id(7);
+        assert_eq!(
+            event.to_frame(),
+            "id: 7\nevent: notification\ndata: first\ndata: second\n\n"
+        );
+    }
+
+    #[test]
+    fn test_pad_frame() {
+        for len in &[
+            0,
+            1,
+            CHUNK_SIZE - 3,
+            CHUNK_SIZE - 2,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            20000,
+        ] {
+            let frame = "x".repeat(*len);
+            let padded = pad_frame(frame.clone());
+            assert_eq!(padded.len() % CHUNK_SIZE, 0);
+            assert_eq!(padded.len() >= len + 2, true);
+            assert_eq!(padded.len() < len + 2 + CHUNK_SIZE, true);
+            // Frame, then a single comment line
+            let padded = String::from_utf8(padded).unwrap();
+            assert_eq!(padded.starts_with(&frame), true);
+            let comment = &padded[frame.len()..];
+            assert_eq!(comment.starts_with(':'), true);
+            assert_eq!(comment.ends_with('\n'), true);
+            assert_eq!(comment.trim_start_matches(':').trim(), "");
+        }
+    }
+
+    #[test]
+    fn test_publish_subscribe() {
+        let hub = EventHub::new(4);
+        let first = hub.subscribe("demo");
+        let second = hub.subscribe("demo");
+        let other = hub.subscribe("other");
+        hub.publish("demo", Event::new("unread", "1".into()));
+        assert_eq!(first.try_recv().unwrap().data, "1");
+        assert_eq!(second.try_recv().unwrap().data, "1");
+        assert_eq!(other.try_recv().is_err(), true);
+        // Closed streams are removed
+        drop(first);
+        drop(second);
+        hub.publish("demo", Event::new("unread", "2".into()));
+        assert_eq!(hub.streams.lock().unwrap().contains_key("demo"), false);
+        assert_eq!(hub.streams.lock().unwrap().contains_key("other"), true);
+        // Nobody listens
+        hub.publish("nobody", Event::new("unread", "3".into()));
+    }
+
+    #[test]
+    fn test_take_slot() {
+        let hub = EventHub::new(2);
+        let first = hub.take_slot();
+        let second = hub.take_slot();
+        assert_eq!(first.is_some() && second.is_some(), true);
+        assert_eq!(hub.take_slot().is_none(), true);
+        drop(first);
+        assert_eq!(hub.take_slot().is_some(), true);
+    }
+
+    #[test]
+    fn test_stream_events() {
+        let hub = EventHub::new(1);
+        let receiver = hub.subscribe("demo");
+        let mut stream = EventStream::new(
+            hub.take_slot().unwrap(),
+            receiver,
+            vec![Event::new("unread", "0".into())],
+            Instant::now() + Duration::from_millis(200),
+            Box::new(|| true),
+        );
+        hub.publish("demo", Event::new("notification", "a".into()).with_id(1));
+        hub.publish("demo", Event::new("notification", "b".into()).with_id(2));
+        let output = read_all(&mut stream);
+        assert_eq!(
+            output.starts_with("retry: 2000\n\nevent: unread\ndata: 0\n\n"),
+            true
+        );
+        // Queued events are sent together in one padded frame
+        assert_eq!(output.len(), 2 * CHUNK_SIZE);
+        assert_eq!(
+            output[CHUNK_SIZE..].starts_with(
+                "id: 1\nevent: notification\ndata: a\n\nid: 2\nevent: notification\ndata: b\n\n:"
+            ),
+            true
+        );
+        // Slot is given back after the lifetime
+        assert_eq!(hub.take_slot().is_some(), true);
+    }
+
+    #[test]
+    fn test_stream_is_alive() {
+        let hub = EventHub::new(1);
+        let started = Instant::now();
+        let mut stream = EventStream::new(
+            hub.take_slot().unwrap(),
+            hub.subscribe("demo"),
+            Vec::new(),
+            started + Duration::from_secs(STREAM_LIFETIME_SECONDS),
+            Box::new(|| false),
+        );
+        stream.live.as_mut().unwrap().check_interval = Duration::from_millis(10);
+        read_all(&mut stream);
+        // Ends at the first check, not at the end of its lifetime
+        assert_eq!(started.elapsed() < Duration::from_secs(5), true);
+        let mut stream = EventStream::busy(vec![Event::new("unread", "0".into())]);
+        assert_eq!(read_all(&mut stream).starts_with("retry: 30000\n\n"), true);
+    }
+}
@@ -36,3 +36,41 @@ pub fn check_email(address: &str) -> AppResult<()> {
     }
     Ok(())
 }
+
+/// # Check link
+/// Accepts a relative path (`/issue/1`) or an absolute
+/// http(s) URL. Anything else, e.g. `javascript:` or
+/// protocol relative `//host` links, is rejected.
+pub fn check_link(link: &str) -> AppResult<()> {
+    let lower = link.to_lowercase();
+    let is_relative = link.starts_with('/') && !link.starts_with("//");
+    let is_http = lower.starts_with("http://") || lower.starts_with("https://");
+    if !(is_relative || is_http)
+        || link.contains('\\')
+        || link.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(BadRequest(
+            "Nem megfelelő link. Csak /-rel kezdődő vagy http(s) link adható meg.".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_link() {
+        assert_eq!(check_link("/issue/abc#comment-2").is_ok(), true);
+        assert_eq!(check_link("https://gnstore.hu/news").is_ok(), true);
+        assert_eq!(check_link("HTTP://gnstore.hu").is_ok(), true);
+        assert_eq!(check_link("javascript:alert(1)").is_ok(), false);
+        assert_eq!(check_link("data:text/html,hello").is_ok(), false);
+        assert_eq!(check_link("//evil.com").is_ok(), false);
+        assert_eq!(check_link("/\\evil.com").is_ok(), false);
+        assert_eq!(check_link("/issue/ 1").is_ok(), false);
+        assert_eq!(check_link("issue/1").is_ok(), false);
+        assert_eq!(check_link("").is_ok(), false);
+    }
+}
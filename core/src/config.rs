@@ -0,0 +1,80 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error::*;
+use crate::prelude::*;
+use std::env;
+use std::fmt::Debug;
+use std::ops::RangeBounds;
+use std::str::FromStr;
+
+/// # Number from ENV
+/// Returns `default` when `key` is not set, and an error
+/// when its value is not a number within `range`.
+pub fn number_from_env<T, R>(key: &str, default: T, range: R) -> AppResult<T>
+where
+    T: FromStr + PartialOrd,
+    R: RangeBounds<T> + Debug,
+{
+    match env::var(key) {
+        Ok(value) => match value.trim().parse::<T>() {
+            Ok(number) if range.contains(&number) => Ok(number),
+            _ => Err(InternalError(format!(
+                "{} must be a number in range {:?}",
+                key, range
+            ))),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_from_env() {
+        assert_eq!(
+            number_from_env("GNSTORE_TEST_MISSING", 7, 1..).ok(),
+            Some(7)
+        );
+        env::set_var("GNSTORE_TEST_NUMBER", " 12 ");
+        assert_eq!(
+            number_from_env("GNSTORE_TEST_NUMBER", 7, 1..).ok(),
+            Some(12)
+        );
+        assert_eq!(
+            number_from_env("GNSTORE_TEST_NUMBER", 7u32, 4..=31).ok(),
+            Some(12)
+        );
+        assert_eq!(
+            number_from_env("GNSTORE_TEST_NUMBER", 7, 1..=10).is_err(),
+            true
+        );
+        env::set_var("GNSTORE_TEST_NEGATIVE", "-3");
+        assert_eq!(
+            number_from_env("GNSTORE_TEST_NEGATIVE", 90i64, 1..).is_err(),
+            true
+        );
+        assert_eq!(
+            number_from_env("GNSTORE_TEST_NEGATIVE", 10usize, 0..).is_err(),
+            true
+        );
+        env::set_var("GNSTORE_TEST_TEXT", "ten");
+        assert_eq!(number_from_env("GNSTORE_TEST_TEXT", 10, 0..).is_err(), true);
+    }
+}
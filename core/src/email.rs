@@ -19,74 +19,202 @@ use crate::check::*;
 use crate::error::Error;
 use crate::error::Error::*;
 use crate::prelude::*;
+use chrono::prelude::*;
 use lettre::smtp::authentication::Credentials;
-use lettre::{SmtpClient, Transport};
+use lettre::{SendableEmail, SmtpClient, Transport};
 use lettre_email;
 use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-pub trait Email<'a> {
-    fn send(&self) -> AppResult<()>;
-    fn is_dummy(&mut self) -> &mut Self;
-}
-
-pub struct EmailData<'a> {
-    to: &'a str,
-    subject: &'a str,
-    body: &'a str,
-    is_dummy: bool,
-}
+// Default directory of the file transport
+const DEFAULT_MAIL_DIR: &str = "data/mail";
 
-pub fn new<'a>(to: &'a str, subject: &'a str, body: &'a str) -> impl Email<'a> {
-    EmailData {
-        to,
-        subject,
-        body,
-        is_dummy: false,
-    }
+/// Email message
+/// Built once, sent by any MailTransport.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    to: String,
+    subject: String,
+    body: String,
+    html: Option<String>,
 }
 
-impl<'a> Email<'a> for EmailData<'a> {
-    // Use it for test email
-    // You can test everything but the SMTP connection.
-    fn is_dummy(&mut self) -> &mut Self {
-        self.is_dummy = true;
-        self
-    }
-    // Try send email. Return AppResult<()>
-    // TODO: Implement some kind of email POOL to manage
-    // connection fail and email stmp temp errors.
-    fn send(&self) -> AppResult<()> {
+impl Message {
+    pub fn new(to: &str, subject: &str, body: &str) -> AppResult<Self> {
         // Validate TO email address
-        check_email(self.to)?;
+        check_email(to)?;
         // Check subject and body
-        if self.subject.len() == 0 || self.body.len() == 0 {
+        if subject.is_empty() || body.is_empty() {
             return Err(InternalError("Empty subject or body.".into()));
         }
-        if self.is_dummy {
-            return Ok(());
+        Ok(Message {
+            to: to.into(),
+            subject: subject.into(),
+            body: body.into(),
+            html: None,
+        })
+    }
+    /// Add HTML alternative
+    /// The plain text body is kept for clients without HTML.
+    pub fn with_html(mut self, html: String) -> Self {
+        self.html = Some(html);
+        self
+    }
+    pub fn get_to(&self) -> &str {
+        &self.to
+    }
+    pub fn get_subject(&self) -> &str {
+        &self.subject
+    }
+    pub fn get_body(&self) -> &str {
+        &self.body
+    }
+    pub fn get_html(&self) -> Option<&str> {
+        self.html.as_deref()
+    }
+    fn build(&self, from: &str) -> AppResult<SendableEmail> {
+        let builder = lettre_email::Email::builder()
+            .to(self.to.as_str())
+            .from(from)
+            .subject(self.subject.as_str());
+        let email: lettre_email::Email = match &self.html {
+            Some(html) => builder.alternative(html.as_str(), self.body.as_str()),
+            None => builder.text(self.body.as_str()),
         }
-        // Lets build it up
-        let email: lettre_email::Email = lettre_email::Email::builder()
-            .to(self.to)
-            .from(env::var("SMTP_FROM_EMAIL")?)
-            .subject(self.subject)
-            .text(self.body)
-            .build()?;
+        .build()?;
+        Ok(email.into())
+    }
+}
 
+/// Mail transport
+/// Delivers a message somewhere, an SMTP server, a directory,
+/// or memory. Selected by ENV, see `transport_from_env()`.
+pub trait MailTransport: Send + Sync {
+    fn send(&self, message: &Message) -> AppResult<()>;
+}
+
+// Shared transport, e.g. a MemoryTransport
+// that is read while the worker uses it.
+impl<T: MailTransport + ?Sized> MailTransport for Arc<T> {
+    fn send(&self, message: &Message) -> AppResult<()> {
+        self.as_ref().send(message)
+    }
+}
+
+/// # SMTP transport
+/// Opens a new connection for each message.
+pub struct SmtpTransport {
+    server: String,
+    username: String,
+    password: String,
+    from: String,
+}
+
+impl SmtpTransport {
+    /// Load config from ENV
+    /// SMTP_SERVER_DOMAIN, SMTP_USERNAME, SMTP_PASSWORD
+    /// and SMTP_FROM_EMAIL are required.
+    pub fn from_env() -> AppResult<Self> {
+        Ok(SmtpTransport {
+            server: env::var("SMTP_SERVER_DOMAIN")?,
+            username: env::var("SMTP_USERNAME")?,
+            password: env::var("SMTP_PASSWORD")?,
+            from: env::var("SMTP_FROM_EMAIL")?,
+        })
+    }
+}
+
+impl MailTransport for SmtpTransport {
+    fn send(&self, message: &Message) -> AppResult<()> {
+        let email = message.build(&self.from)?;
         // Open a remote connection to SMTP server
-        SmtpClient::new_simple(&env::var("SMTP_SERVER_DOMAIN")?)
-            .unwrap()
+        SmtpClient::new_simple(&self.server)?
             .credentials(Credentials::new(
-                env::var("SMTP_USERNAME")?,
-                env::var("SMTP_PASSWORD")?,
+                self.username.clone(),
+                self.password.clone(),
             ))
             .transport()
-            .send(email.into())?;
+            .send(email)?;
+        Ok(())
+    }
+}
+
+/// # File transport
+/// Writes each message into an .eml file,
+/// for development environments.
+pub struct FileTransport {
+    dir: PathBuf,
+    from: String,
+}
 
+impl FileTransport {
+    pub fn new(dir: PathBuf, from: &str) -> AppResult<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(FileTransport {
+            dir,
+            from: from.into(),
+        })
+    }
+}
+
+impl MailTransport for FileTransport {
+    fn send(&self, message: &Message) -> AppResult<()> {
+        let email = message.build(&self.from)?;
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%d%H%M%S"),
+            email.message_id()
+        );
+        fs::write(self.dir.join(file_name), email.message_to_string()?)?;
+        Ok(())
+    }
+}
+
+/// # Memory transport
+/// Keeps every sent message in memory,
+/// so tests can check what was sent.
+#[derive(Default)]
+pub struct MemoryTransport {
+    sent: Mutex<Vec<Message>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        MemoryTransport::default()
+    }
+    /// Messages sent so far
+    pub fn sent(&self) -> Vec<Message> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl MailTransport for MemoryTransport {
+    fn send(&self, message: &Message) -> AppResult<()> {
+        self.sent.lock().unwrap().push(message.clone());
         Ok(())
     }
 }
 
+/// # Transport from ENV
+/// MAIL_TRANSPORT selects the transport: smtp (default),
+/// file or memory. The file transport writes into MAIL_DIR,
+/// default is data/mail.
+pub fn transport_from_env() -> AppResult<Arc<dyn MailTransport>> {
+    let kind = env::var("MAIL_TRANSPORT").unwrap_or("smtp".into());
+    match kind.as_str() {
+        "smtp" => Ok(Arc::new(SmtpTransport::from_env()?)),
+        "file" => Ok(Arc::new(FileTransport::new(
+            PathBuf::from(env::var("MAIL_DIR").unwrap_or(DEFAULT_MAIL_DIR.into())),
+            &env::var("SMTP_FROM_EMAIL").unwrap_or("gnstore@localhost.local".into()),
+        )?)),
+        "memory" => Ok(Arc::new(MemoryTransport::new())),
+        _ => Err(InternalError(format!("Unknown MAIL_TRANSPORT: {}", kind))),
+    }
+}
+
 impl From<lettre_email::error::Error> for Error {
     fn from(error: lettre_email::error::Error) -> Self {
         InternalError(format!("{}", error))
@@ -105,14 +233,58 @@ impl From<lettre::smtp::error::Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        InternalError(format!("{}", error))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
-    fn test_send_email() {
-        new("mezeipetister@gmail.com", "Subject", "Body")
-            .is_dummy()
-            .send()
+    fn test_new_message() {
+        assert_eq!(Message::new("demo", "Subject", "Body").is_ok(), false);
+        assert_eq!(Message::new("demo@user.com", "", "Body").is_ok(), false);
+        assert_eq!(
+            Message::new("demo@user.com", "Subject", "Body").is_ok(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_memory_transport() {
+        let transport = MemoryTransport::new();
+        let message = Message::new("mezeipetister@gmail.com", "Subject", "Body").unwrap();
+        transport.send(&message).unwrap();
+        assert_eq!(transport.sent(), vec![message]);
+    }
+
+    #[test]
+    fn test_multipart_message() {
+        let email = Message::new("demo@user.com", "Subject", "Body")
+            .unwrap()
+            .with_html("<p>Body</p>".into())
+            .build("store@demo.com")
+            .unwrap()
+            .message_to_string()
             .unwrap();
+        assert_eq!(email.contains("multipart/alternative"), true);
+        assert_eq!(email.contains("text/html"), true);
+        assert_eq!(email.contains("text/plain"), true);
+    }
+
+    #[test]
+    fn test_file_transport() {
+        let dir = env::temp_dir().join(format!("gnstore_mail_{}", crate::generate_token_id()));
+        let transport = FileTransport::new(dir.clone(), "store@demo.com").unwrap();
+        let message = Message::new("demo@user.com", "Subject", "Body").unwrap();
+        transport.send(&message).unwrap();
+        let files = fs::read_dir(&dir).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let content = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert_eq!(content.contains("Subject: Subject"), true);
+        assert_eq!(content.contains("text/html"), false);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -20,6 +20,7 @@ use storaget;
 
 pub enum Error {
     BadRequest(String),
+    Forbidden(String),
     InternalError(String),
 }
 
@@ -29,6 +30,7 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::BadRequest(msg) => write!(f, "{}", msg),
+            Error::Forbidden(msg) => write!(f, "{}", msg),
             Error::InternalError(msg) => write!(f, "Internal error: {}", msg),
         }
     }
@@ -38,6 +40,7 @@ impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::BadRequest(msg) => write!(f, "{}", msg),
+            Error::Forbidden(msg) => write!(f, "{}", msg),
             Error::InternalError(msg) => write!(f, "Internal error: {}", msg),
         }
     }
@@ -15,7 +15,14 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::error::Error::{self, BadRequest};
+use crate::model::Issue;
+use crate::prelude::AppResult;
+use chrono::prelude::*;
 use nanoid::nanoid;
+use std::cmp::Ordering;
+use std::str::FromStr;
+use storaget::*;
 
 pub fn generate_issue_id() -> String {
     nanoid!(
@@ -27,3 +34,405 @@ pub fn generate_issue_id() -> String {
         ]
     )
 }
+
+/// Reference found in a text
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reference {
+    /// @userid mention, lowercase
+    User(String),
+    /// #number issue reference
+    Issue(usize),
+}
+
+/// Reference with its byte range in the text
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceSpan {
+    pub start: usize,
+    pub end: usize,
+    pub reference: Reference,
+}
+
+/// Scan @userid mentions and #number issue references
+/// User IDs are lowercase english characters, numbers and _,
+/// so a mention ends at the first other character.
+/// The @ must not follow a word character, this way
+/// email addresses are not mentions.
+/// The # must not follow a word character or &,
+/// this way HTML entities like &#123; are not references,
+/// and the number must end the word, so #12abc is not one.
+pub fn scan_references(text: &str) -> Vec<ReferenceSpan> {
+    let is_id_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut result: Vec<ReferenceSpan> = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '@' if !previous.map(is_id_char).unwrap_or(false) => {
+                let mut mention = String::new();
+                while let Some((_, next)) = chars.peek() {
+                    if !is_id_char(*next) {
+                        break;
+                    }
+                    mention.push(next.to_ascii_lowercase());
+                    chars.next();
+                }
+                previous = mention.chars().last().or(Some(c));
+                if !mention.is_empty() {
+                    result.push(ReferenceSpan {
+                        start,
+                        end: start + 1 + mention.len(),
+                        reference: Reference::User(mention),
+                    });
+                }
+            }
+            '#' if !previous
+                .map(|p| is_word_char(p) || p == '&')
+                .unwrap_or(false) =>
+            {
+                let mut digits = String::new();
+                while let Some((_, next)) = chars.peek() {
+                    if !next.is_ascii_digit() {
+                        break;
+                    }
+                    digits.push(*next);
+                    chars.next();
+                }
+                let ends_word = chars.peek().map(|(_, n)| !is_word_char(*n)).unwrap_or(true);
+                previous = digits.chars().last().or(Some(c));
+                if let (true, Ok(number)) = (ends_word, digits.parse::<usize>()) {
+                    if number > 0 {
+                        result.push(ReferenceSpan {
+                            start,
+                            end: start + 1 + digits.len(),
+                            reference: Reference::Issue(number),
+                        });
+                    }
+                }
+            }
+            _ => previous = Some(c),
+        }
+    }
+    result
+}
+
+/// Parse @userid mentions from a text
+/// Returns unique lowercase user IDs in order of appearance.
+pub fn parse_mentions(text: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for span in scan_references(text) {
+        if let Reference::User(userid) = span.reference {
+            if !result.contains(&userid) {
+                result.push(userid);
+            }
+        }
+    }
+    result
+}
+
+/// Parse #number issue references from a text
+/// Returns unique numbers in order of appearance.
+pub fn parse_issue_references(text: &str) -> Vec<usize> {
+    let mut result: Vec<usize> = Vec::new();
+    for span in scan_references(text) {
+        if let Reference::Issue(number) = span.reference {
+            if !result.contains(&number) {
+                result.push(number);
+            }
+        }
+    }
+    result
+}
+
+/// Mentions of an edited text that were not
+/// in its @previous version, to notify only them.
+pub fn added_mentions(previous: &str, mentions: &[String]) -> Vec<String> {
+    let previous = parse_mentions(previous);
+    mentions
+        .iter()
+        .filter(|userid| !previous.contains(userid))
+        .cloned()
+        .collect()
+}
+
+/// Issue references of an edited @text that were
+/// not in its @previous version.
+pub fn added_issue_references(previous: &str, text: &str) -> Vec<usize> {
+    let previous = parse_issue_references(previous);
+    parse_issue_references(text)
+        .into_iter()
+        .filter(|number| !previous.contains(number))
+        .collect()
+}
+
+/// Parse issue number from "142" or "#142"
+pub fn parse_issue_number(value: &str) -> Option<usize> {
+    value
+        .trim()
+        .trim_start_matches('#')
+        .parse::<usize>()
+        .ok()
+        .filter(|n| *n > 0)
+}
+
+/// # Issue filter
+/// Every set field must match, unset fields match all issues.
+#[derive(Debug, Clone, Default)]
+pub struct IssueFilter {
+    /// true for open, false for closed issues
+    pub is_open: Option<bool>,
+    pub assigned_to: Option<String>,
+    pub created_by: Option<String>,
+    /// IssueLabel ID
+    pub label: Option<String>,
+    pub followed_by: Option<String>,
+    /// Created on or after this day
+    pub created_from: Option<NaiveDate>,
+    /// Created on or before this day
+    pub created_to: Option<NaiveDate>,
+    /// Free text, case insensitive search over
+    /// title, description and comments
+    pub text: Option<String>,
+}
+
+impl IssueFilter {
+    pub fn matches(&self, issue: &Issue) -> bool {
+        let day = issue.get_date_created().naive_utc().date();
+        self.is_open.is_none_or(|o| issue.get_is_open() == o)
+            && self
+                .assigned_to
+                .as_ref()
+                .is_none_or(|u| issue.get_assigned_to() == *u)
+            && self
+                .created_by
+                .as_ref()
+                .is_none_or(|u| issue.get_created_by() == *u)
+            && self
+                .label
+                .as_ref()
+                .is_none_or(|l| issue.get_label_ids().contains(l))
+            && self
+                .followed_by
+                .as_ref()
+                .is_none_or(|u| issue.get_followed_by().contains(u))
+            && self.created_from.is_none_or(|from| day >= from)
+            && self.created_to.is_none_or(|to| day <= to)
+            && self.text.as_ref().is_none_or(|t| matches_text(issue, t))
+    }
+}
+
+fn matches_text(issue: &Issue, text: &str) -> bool {
+    let text = text.trim().to_lowercase();
+    if text.is_empty() {
+        return true;
+    }
+    issue.get_title().to_lowercase().contains(&text)
+        || issue.get_description().to_lowercase().contains(&text)
+        || issue
+            .get_comments()
+            .iter()
+            .any(|c| c.get_text().to_lowercase().contains(&text))
+}
+
+/// Parse date filter value in YYYY-MM-DD format
+pub fn parse_date(value: &str) -> AppResult<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").map_err(|_| {
+        BadRequest(format!(
+            "Hibás dátum: {}, helyes formátum: ÉÉÉÉ-HH-NN",
+            value
+        ))
+    })
+}
+
+/// # Issue sort order
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IssueSort {
+    /// By creation date
+    #[default]
+    Created,
+    /// By the date of the last event
+    Updated,
+    /// By number of comments
+    Comments,
+    /// By title, case insensitive
+    Title,
+}
+
+impl FromStr for IssueSort {
+    type Err = Error;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "created" => Ok(IssueSort::Created),
+            "updated" => Ok(IssueSort::Updated),
+            "comments" => Ok(IssueSort::Comments),
+            "title" => Ok(IssueSort::Title),
+            _ => Err(BadRequest(format!("Ismeretlen rendezés: {}", name))),
+        }
+    }
+}
+
+impl IssueSort {
+    /// Compare two issues in ascending order
+    /// Equal ones are ordered by creation date and ID,
+    /// so pages are stable.
+    pub fn compare(&self, a: &Issue, b: &Issue) -> Ordering {
+        let ordering = match self {
+            IssueSort::Created => a.get_date_created().cmp(&b.get_date_created()),
+            IssueSort::Updated => a.get_date_updated().cmp(&b.get_date_updated()),
+            IssueSort::Comments => a.get_comment_count().cmp(&b.get_comment_count()),
+            IssueSort::Title => a
+                .get_title()
+                .to_lowercase()
+                .cmp(&b.get_title().to_lowercase()),
+        };
+        ordering
+            .then_with(|| a.get_date_created().cmp(&b.get_date_created()))
+            .then_with(|| a.get_id().cmp(b.get_id()))
+    }
+    /// Sort issues, @descending reverses the order
+    pub fn sort(&self, issues: &mut [Issue], descending: bool) {
+        issues.sort_by(|a, b| {
+            let ordering = self.compare(a, b);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        assert_eq!(
+            parse_mentions("@peter please check it with @Anna_1."),
+            vec!["peter".to_string(), "anna_1".to_string()]
+        );
+        assert_eq!(
+            parse_mentions("(@peter) @peter, @anna"),
+            vec!["peter".to_string(), "anna".to_string()]
+        );
+        assert_eq!(
+            parse_mentions("mail me: peter@example.com"),
+            Vec::<String>::new()
+        );
+        assert_eq!(parse_mentions("@ alone and @@x"), vec!["x".to_string()]);
+        assert_eq!(parse_mentions("no mention"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_scan_references() {
+        let text = "Hi @Peter, see #12!";
+        let spans = scan_references(text);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(&text[spans[0].start..spans[0].end], "@Peter");
+        assert_eq!(spans[0].reference, Reference::User("peter".into()));
+        assert_eq!(&text[spans[1].start..spans[1].end], "#12");
+        assert_eq!(spans[1].reference, Reference::Issue(12));
+    }
+
+    #[test]
+    fn test_parse_issue_references() {
+        assert_eq!(
+            parse_issue_references("Same as #142, see also (#7) and #142."),
+            vec![142, 7]
+        );
+        assert_eq!(
+            parse_issue_references("a#1 &#123; #12abc #0 # 5"),
+            Vec::<usize>::new()
+        );
+        assert_eq!(parse_issue_number("#142"), Some(142));
+        assert_eq!(parse_issue_number("142"), Some(142));
+        assert_eq!(parse_issue_number("abc"), None);
+    }
+
+    #[test]
+    fn test_added_references() {
+        let mentions = vec!["peter".to_string(), "anna".to_string()];
+        assert_eq!(
+            added_mentions("Hi @Peter and @bob", &mentions),
+            vec!["anna".to_string()]
+        );
+        assert_eq!(added_mentions("", &mentions), mentions);
+        assert_eq!(
+            added_issue_references("See #7 and #12", "See #12, #7 and #142"),
+            vec![142]
+        );
+        assert_eq!(
+            added_issue_references("See #7", "Nothing"),
+            Vec::<usize>::new()
+        );
+    }
+
+    fn issue(title: &str, created_by: &str) -> Issue {
+        Issue::new(title.into(), "Description".into(), created_by.into())
+    }
+
+    #[test]
+    fn test_issue_filter() {
+        let mut first = issue("Broken pump", "peter");
+        first.add_comment("Needs a new valve".into(), "anna".into(), Vec::new());
+        first.attach_label("bug".into(), "peter".into());
+        let mut second = issue("Order seeds", "anna");
+        second.close_issue("anna".into());
+
+        let filter = IssueFilter::default();
+        assert!(filter.matches(&first) && filter.matches(&second));
+
+        let filter = IssueFilter {
+            is_open: Some(false),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&first) && filter.matches(&second));
+
+        let filter = IssueFilter {
+            text: Some("VALVE".into()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&first) && !filter.matches(&second));
+
+        let filter = IssueFilter {
+            label: Some("bug".into()),
+            created_by: Some("peter".into()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&first) && !filter.matches(&second));
+
+        let today = Utc::now().naive_utc().date();
+        let filter = IssueFilter {
+            created_from: Some(today),
+            created_to: Some(today),
+            followed_by: Some("anna".into()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&first) && filter.matches(&second));
+        let filter = IssueFilter {
+            created_from: Some(today.succ()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&first));
+    }
+
+    #[test]
+    fn test_issue_sort() {
+        let mut a = issue("beta", "peter");
+        a.add_comment("First".into(), "peter".into(), Vec::new());
+        let b = issue("Alpha", "peter");
+        let mut issues = vec![a, b];
+        IssueSort::Title.sort(&mut issues, false);
+        assert_eq!(issues[0].get_title(), "Alpha");
+        IssueSort::Comments.sort(&mut issues, true);
+        assert_eq!(issues[0].get_title(), "beta");
+        assert_eq!("updated".parse::<IssueSort>().unwrap(), IssueSort::Updated);
+        assert!("unknown".parse::<IssueSort>().is_err());
+        assert_eq!(
+            parse_date("2020-03-01").unwrap(),
+            NaiveDate::from_ymd(2020, 3, 1)
+        );
+        assert!(parse_date("2020.03.01").is_err());
+    }
+}
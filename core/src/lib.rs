@@ -15,24 +15,32 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+extern crate ammonia;
 extern crate bcrypt;
 extern crate chrono;
+extern crate crypto;
 extern crate lettre;
 extern crate lettre_email;
 extern crate nanoid;
+extern crate pulldown_cmark;
 extern crate rand;
 extern crate storaget;
 
 pub mod check;
+pub mod config;
 pub mod customer;
 pub mod email;
 pub mod error;
 pub mod issue;
 pub mod login;
+pub mod markdown;
 pub mod model;
 pub mod notification;
 pub mod password;
 pub mod prelude;
+pub mod template;
+pub mod throttle;
+pub mod totp;
 pub mod user;
 
 pub use check::*;
@@ -15,48 +15,191 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
-/// ```
-pub fn login<'a>(email: &'a str, password: &'a str) -> Result<&'a str, String> {
-    let _ = email;
-    let _ = password;
-    Err("Not implemented".to_owned())
+use crate::error::Error::*;
+use crate::model::{Session, User};
+use crate::password::{needs_rehash, verify_password_from_hash};
+use crate::prelude::*;
+use chrono::prelude::*;
+use chrono::Duration;
+use nanoid::nanoid;
+
+/// # Generate token ID
+/// Random ID used as the `jti` claim of an access token,
+/// so every issued token can be identified on its own.
+pub fn generate_token_id() -> String {
+    nanoid!(
+        24,
+        &[
+            'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q',
+            'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7',
+            '8', '9',
+        ]
+    )
+}
+
+/// # Login function
+/// Verify the given password against the user password hash.
+/// If it matches, returns a new Session valid for `expires_in`.
+/// The caller should store the session in the session registry,
+/// and use its ID as the token ID.
+/// Failed attempts are counted on the user, and after too many
+/// failures the login is locked for a while. A locked or disabled
+/// user gets the same error as a wrong password.
+pub fn login(user: &mut User, password: &str, expires_in: Duration) -> AppResult<Session> {
+    let now = Utc::now();
+    if user.is_disabled() || user.is_login_locked(now) || user.get_password_hash().is_empty() {
+        return Err(BadRequest("Helytelen belépési adatok".to_owned()));
+    }
+    if !verify_password_from_hash(password, user.get_password_hash())? {
+        user.register_failed_login(now);
+        return Err(BadRequest("Helytelen belépési adatok".to_owned()));
+    }
+    user.reset_failed_logins();
+    // Upgrade hashes created with a lower cost,
+    // this is the only time we know the password.
+    if needs_rehash(user.get_password_hash()) {
+        user.rehash_password(password)?;
+    }
+    Ok(Session::new(
+        user.get_user_id().to_string(),
+        now + expires_in,
+    ))
 }
 
 /// # Logout function
-/// Check the user login status, and try to log out. If the user is valid,
-/// and logged in, then removes from the logged-in list. The controller
+/// Check the session status, and try to log out. If the session is valid,
+/// then revokes it, so it is no longer in the logged-in list. The controller
 /// should delete the user-token from the browser. If the user tries to
-/// access the system using the
-pub fn logout(token: &str) -> Result<String, String> {
-    let _ = token;
-    Err("Not implemented".to_owned())
+/// access the system using the revoked token, the validation fails.
+pub fn logout(session: &mut Session) -> AppResult<()> {
+    if session.revoke() {
+        Ok(())
+    } else {
+        Err(BadRequest(
+            "A munkamenet már ki van jelentkeztetve".to_owned(),
+        ))
+    }
 }
 
 /// # Validate access token
-/// Get a user access token, and validate it. If the token valid,
-/// and its in the logged-in list, then return Ok(user-id), if the
-/// token is unvalid, or its not in the logged-in list, then return
-/// Err("Error message").
-pub fn validate_access_token(token: &str) -> Result<String, String> {
-    let _ = token;
-    Err("Not implemented".to_owned())
+/// Get the session belongs to an access token, and validate it.
+/// If the session is active - not revoked and not expired -,
+/// then return Ok(user-id), otherwise return Err("Error message").
+pub fn validate_access_token(session: &Session) -> AppResult<String> {
+    if session.is_active_at(Utc::now()) {
+        Ok(session.get_userid().to_string())
+    } else {
+        Err(BadRequest(
+            "Lejárt vagy kijelentkeztetett munkamenet".to_owned(),
+        ))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn demo_user() -> User {
+        let mut user = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        user.set_password("GreenHouse42".into()).unwrap();
+        user
+    }
+
     #[test]
     fn test_login() {
-        assert_eq!(login("email", "password").is_ok(), false); // Should be false.
+        let mut user = demo_user();
+        assert_eq!(
+            login(&mut user, "password", Duration::hours(1)).is_ok(),
+            false
+        ); // Should be false.
+        assert_eq!(user.get_login_attempts().get_failed_count(), 1);
+        let session = login(&mut user, "GreenHouse42", Duration::hours(1)).unwrap();
+        assert_eq!(session.get_userid(), "demo");
+        assert_eq!(user.get_login_attempts().get_failed_count(), 0);
+    }
+
+    #[test]
+    fn test_login_rehash() {
+        let mut user = demo_user();
+        // Hash created with an old, lower cost
+        user.password_hash = bcrypt::hash("GreenHouse42", 6).unwrap();
+        assert_eq!(needs_rehash(user.get_password_hash()), true);
+        login(&mut user, "GreenHouse42", Duration::hours(1)).unwrap();
+        assert_eq!(needs_rehash(user.get_password_hash()), false);
+        assert_eq!(
+            verify_password_from_hash("GreenHouse42", user.get_password_hash()).unwrap(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_login_without_password() {
+        let mut user = demo_user();
+        user.password_hash = "".into();
+        assert_eq!(login(&mut user, "", Duration::hours(1)).is_ok(), false);
+    }
+
+    #[test]
+    fn test_login_disabled_user() {
+        let mut user = demo_user();
+        user.disable();
+        assert_eq!(
+            login(&mut user, "GreenHouse42", Duration::hours(1)).is_ok(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_login_lockout() {
+        let mut user = demo_user();
+        for _ in 0..5 {
+            assert_eq!(login(&mut user, "wrong", Duration::hours(1)).is_ok(), false);
+        }
+        // Locked, even the right password fails
+        assert_eq!(user.is_login_locked(Utc::now()), true);
+        assert_eq!(
+            login(&mut user, "GreenHouse42", Duration::hours(1)).is_ok(),
+            false
+        );
+        user.reset_failed_logins();
+        assert_eq!(
+            login(&mut user, "GreenHouse42", Duration::hours(1)).is_ok(),
+            true
+        );
     }
 
     #[test]
     fn test_logout() {
-        assert_eq!(logout("token").is_ok(), false);
+        let mut session = login(&mut demo_user(), "GreenHouse42", Duration::hours(1)).unwrap();
+        assert_eq!(logout(&mut session).is_ok(), true);
+        // Second logout should fail
+        assert_eq!(logout(&mut session).is_ok(), false);
+    }
+
+    #[test]
+    fn test_generate_token_id() {
+        assert_eq!(generate_token_id().len(), 24);
+        assert_ne!(generate_token_id(), generate_token_id());
     }
 
     #[test]
     fn test_validate_token() {
-        assert_eq!(validate_access_token("token").is_ok(), false);
+        let mut session = login(&mut demo_user(), "GreenHouse42", Duration::hours(1)).unwrap();
+        assert_eq!(validate_access_token(&session).unwrap(), "demo");
+        logout(&mut session).unwrap();
+        assert_eq!(validate_access_token(&session).is_ok(), false);
+    }
+
+    #[test]
+    fn test_validate_expired_token() {
+        let session = Session::new("demo".into(), Utc::now() - Duration::seconds(1));
+        assert_eq!(validate_access_token(&session).is_ok(), false);
     }
 }
@@ -0,0 +1,244 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Markdown rendering
+//! CommonMark with tables, task lists and strikethrough,
+//! rendered to sanitized HTML. Raw HTML in the source is
+//! allowed, but everything unsafe is removed by the sanitizer.
+
+use crate::issue::{scan_references, Reference};
+use ammonia::Builder;
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Known references of a text
+/// Only these are autolinked, so unknown users
+/// and missing issues stay plain text.
+#[derive(Debug, Clone, Default)]
+pub struct References {
+    pub users: Vec<String>,
+    pub issues: Vec<usize>,
+}
+
+/// Task list progress
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct TaskProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+impl TaskProgress {
+    pub fn add(&mut self, other: TaskProgress) {
+        self.done += other.done;
+        self.total += other.total;
+    }
+}
+
+fn options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options
+}
+
+/// Render markdown to sanitized HTML
+/// Known @userid and #number references are linked
+/// to /user/<id> and /issue/<number>, except in code and links.
+pub fn render(text: &str, references: &References) -> String {
+    let mut events: Vec<Event> = Vec::new();
+    // Depth of links, images and code blocks, no autolink inside them
+    let mut skip_depth = 0;
+    // Consecutive text events are merged,
+    // so a reference cannot be split
+    let mut pending = String::new();
+    for event in Parser::new_ext(text, options()) {
+        match event {
+            Event::Text(text) if skip_depth == 0 => {
+                pending.push_str(&text);
+                continue;
+            }
+            _ => (),
+        }
+        if !pending.is_empty() {
+            events.push(Event::Html(link_references(&pending, references).into()));
+            pending.clear();
+        }
+        match &event {
+            Event::Start(Tag::Link(..))
+            | Event::Start(Tag::Image(..))
+            | Event::Start(Tag::CodeBlock(_)) => skip_depth += 1,
+            Event::End(Tag::Link(..))
+            | Event::End(Tag::Image(..))
+            | Event::End(Tag::CodeBlock(_)) => skip_depth -= 1,
+            _ => (),
+        }
+        events.push(event);
+    }
+    if !pending.is_empty() {
+        events.push(Event::Html(link_references(&pending, references).into()));
+    }
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    sanitize(&unsafe_html)
+}
+
+/// Remove everything unsafe from HTML
+/// Task list checkboxes are kept, every input
+/// is forced to be a disabled checkbox.
+pub fn sanitize(unsafe_html: &str) -> String {
+    Builder::default()
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["checked"])
+        .set_tag_attribute_value("input", "type", "checkbox")
+        .set_tag_attribute_value("input", "disabled", "")
+        .clean(unsafe_html)
+        .to_string()
+}
+
+/// Escape text, and replace known references with links
+fn link_references(text: &str, references: &References) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for span in scan_references(text) {
+        let link = match &span.reference {
+            Reference::User(userid) if references.users.contains(userid) => {
+                format!("/user/{}", userid)
+            }
+            Reference::Issue(number) if references.issues.contains(number) => {
+                format!("/issue/{}", number)
+            }
+            _ => continue,
+        };
+        escape_into(&mut result, &text[last..span.start]);
+        result.push_str(&format!("<a href=\"{}\">", link));
+        escape_into(&mut result, &text[span.start..span.end]);
+        result.push_str("</a>");
+        last = span.end;
+    }
+    escape_into(&mut result, &text[last..]);
+    result
+}
+
+fn escape_into(result: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c),
+        }
+    }
+}
+
+/// Count checked and all task list items
+pub fn task_progress(text: &str) -> TaskProgress {
+    let mut progress = TaskProgress::default();
+    for event in Parser::new_ext(text, options()) {
+        if let Event::TaskListMarker(checked) = event {
+            progress.total += 1;
+            if checked {
+                progress.done += 1;
+            }
+        }
+    }
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn references() -> References {
+        References {
+            users: vec!["peter".into()],
+            issues: vec![12],
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("**Hello** _world_", &References::default()),
+            "<p><strong>Hello</strong> <em>world</em></p>\n"
+        );
+        let table = render("| a | b |\n|---|---|\n| 1 | 2 |", &References::default());
+        assert!(table.contains("<table>"));
+        assert!(table.contains("<td>1</td>"));
+    }
+
+    #[test]
+    fn test_render_xss() {
+        let html = render(
+            "<script>alert(1)</script>\n\nText <img src=x onerror=alert(1)> [x](javascript:alert(1))",
+            &References::default(),
+        );
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("javascript:"));
+        let html = render("a <b> & c", &References::default());
+        assert!(html.contains("&amp;"));
+        // Only disabled checkboxes
+        let html = render(
+            "<input type=\"text\" value=\"x\"> <input type=\"password\"> <input type=\"submit\" formaction=\"/logout\">",
+            &References::default(),
+        );
+        assert!(!html.contains("text"));
+        assert!(!html.contains("password"));
+        assert!(!html.contains("submit"));
+        assert!(!html.contains("formaction"));
+        assert_eq!(html.matches("type=\"checkbox\"").count(), 3);
+        assert_eq!(html.matches("disabled").count(), 3);
+    }
+
+    #[test]
+    fn test_render_references() {
+        let html = render("@peter see #12, not @anna or #13", &references());
+        assert!(html.contains("<a href=\"/user/peter\" rel=\"noopener noreferrer\">@peter</a>"));
+        assert!(html.contains("<a href=\"/issue/12\" rel=\"noopener noreferrer\">#12</a>"));
+        assert!(html.contains("@anna"));
+        assert!(!html.contains("/issue/13"));
+        // No autolink in code
+        let html = render("`#12` and\n\n```\n@peter\n```", &references());
+        assert!(!html.contains("<a "));
+        // No autolink inside links
+        let html = render("[see #12](https://example.com)", &references());
+        assert!(!html.contains("/issue/12"));
+        // Image alt text is escaped once, without links
+        let html = render("![@peter & #12](/logo.png)", &references());
+        assert!(html.contains("alt=\"@peter &amp; #12\""));
+        assert!(!html.contains("<a "));
+        // Text is escaped
+        let html = render("1 < 2 @peter", &references());
+        assert!(html.contains("1 &lt; 2"));
+    }
+
+    #[test]
+    fn test_task_progress() {
+        let text = "- [x] Buy seeds\n- [ ] Plant them\n- [X] Water\n- no task";
+        assert_eq!(task_progress(text), TaskProgress { done: 2, total: 3 });
+        let html = render(text, &References::default());
+        assert!(html.contains("<input"));
+        assert!(html.contains("checkbox"));
+        let mut progress = TaskProgress::default();
+        progress.add(task_progress(text));
+        progress.add(task_progress("- [ ] One more"));
+        assert_eq!(progress, TaskProgress { done: 2, total: 4 });
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+pub use crate::model::version::counter::v1::Counter;
+
+// Counter ID of the issue numbers
+pub const ISSUE_COUNTER: &str = "issue";
+
+impl Counter {
+    pub fn new(id: &str) -> Self {
+        Counter {
+            id: id.to_string(),
+            value: 0,
+        }
+    }
+    /**
+     * Increment the counter and return the new value
+     * First value is 1
+     */
+    pub fn next_value(&mut self) -> usize {
+        self.value += 1;
+        self.value
+    }
+    /**
+     * Get the last value given out
+     */
+    pub fn get_value(&self) -> usize {
+        self.value
+    }
+    /**
+     * Make sure the next value is greater than @value
+     */
+    pub fn set_at_least(&mut self, value: usize) {
+        if self.value < value {
+            self.value = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter() {
+        let mut counter = Counter::new(ISSUE_COUNTER);
+        assert_eq!(counter.next_value(), 1);
+        assert_eq!(counter.next_value(), 2);
+        counter.set_at_least(1);
+        assert_eq!(counter.get_value(), 2);
+        counter.set_at_least(10);
+        assert_eq!(counter.next_value(), 11);
+    }
+}
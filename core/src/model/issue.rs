@@ -16,6 +16,7 @@
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::issue::*;
+use crate::markdown::{task_progress, TaskProgress};
 pub use crate::model::version::issue::comment::v1::Comment;
 pub use crate::model::version::issue::event::v1::{Event, EventKind};
 pub use crate::model::version::issue::label::v1::Label;
@@ -26,20 +27,58 @@ use chrono::prelude::*;
 
 impl Issue {
     pub fn new(title: String, description: String, created_by: String) -> Self {
-        Issue {
-            // TODO: Change it to have a counter and use usize instead!
+        let mut issue = Issue {
+            // Storage ID stays random, use set_number
+            // to give it a sequential number from the issue counter.
             id: generate_issue_id(),
+            number: 0,
             title,
             description,
             date_created: Utc::now(),
             created_by: created_by.clone(),
             labels: Vec::new(),
+            label_ids: Vec::new(),
             assigned_to: created_by,
             events: Vec::new(),
             comment_count: 0,
             followed_by: Vec::new(),
             is_open: true,
-        }
+        };
+        // Creator follows its own issue
+        issue.follow(issue.get_created_by());
+        issue
+    }
+    /**
+     * Get sequential issue number
+     */
+    pub fn get_number(&self) -> usize {
+        self.number
+    }
+    /**
+     * Set sequential issue number
+     */
+    pub fn set_number(&mut self, number: usize) {
+        self.number = number;
+    }
+    /**
+     * Add cross-reference event
+     * when a comment of another issue refers to this one
+     */
+    pub fn add_reference(
+        &mut self,
+        issue_id: String,
+        number: usize,
+        comment_id: usize,
+        created_by: String,
+    ) {
+        self.events.push(Event::new(
+            created_by,
+            EventKind::Referenced {
+                id: issue_id,
+                number,
+                comment_id,
+            },
+        ));
     }
     /**
      * Get title
@@ -53,12 +92,56 @@ impl Issue {
     pub fn set_title(&mut self, title: String) {
         self.title = title;
     }
+    /**
+     * Change title and keep the previous one
+     * in a TitleChanged event
+     */
+    pub fn edit_title(&mut self, title: String, created_by: String) -> AppResult<()> {
+        let title = title.trim().to_string();
+        if title.is_empty() {
+            return Err(Error::BadRequest("Az issue címe nem lehet üres".to_owned()));
+        }
+        if title == self.title {
+            return Ok(());
+        }
+        let previous = std::mem::replace(&mut self.title, title);
+        self.events
+            .push(Event::new(created_by, EventKind::TitleChanged { previous }));
+        Ok(())
+    }
     /**
      * Get description
      */
     pub fn get_description(&self) -> String {
         self.description.clone()
     }
+    /**
+     * Change description and keep the previous one
+     * in a DescriptionChanged event
+     * Mentioned users start to follow the issue.
+     * Returns the previous description if it has changed.
+     */
+    pub fn edit_description(
+        &mut self,
+        description: String,
+        created_by: String,
+        mentions: Vec<String>,
+    ) -> Option<String> {
+        if description == self.description {
+            return None;
+        }
+        for user_id in mentions {
+            self.follow(user_id);
+        }
+        let previous = std::mem::replace(&mut self.description, description);
+        self.events.push(Event::new(
+            created_by,
+            EventKind::DescriptionChanged {
+                previous: previous.clone(),
+            },
+        ));
+        Some(previous)
+    }
     /**
      * Get userid created by
      */
@@ -73,38 +156,56 @@ impl Issue {
         self.date_created
     }
     /**
-     * Get related labels
+     * Get legacy labels
+     * Empty after the label migration
      */
     pub fn get_labels(&self) -> Vec<Label> {
         self.labels.clone()
     }
     /**
-     * Add label to issue
+     * Get assigned IssueLabel IDs
      */
-    pub fn add_label(&mut self, label: Label, created_by: String) {
-        // Check if the label is already in the label list
-        if let Some(_) = self.labels.iter().position(|l| *l == label) {
+    pub fn get_label_ids(&self) -> &Vec<String> {
+        &self.label_ids
+    }
+    /**
+     * Attach label by its IssueLabel ID
+     * and create an event about it
+     */
+    pub fn attach_label(&mut self, label_id: String, created_by: String) {
+        if self.label_ids.contains(&label_id) {
             return;
         }
-        // If the label is not in the vector,
-        // then we add it
-        self.labels.push(label.clone());
-        // And now add the label to the event list
+        self.label_ids.push(label_id.clone());
         self.events
-            .push(Event::new(created_by, EventKind::LabelAdded(label)));
+            .push(Event::new(created_by, EventKind::LabelAttached(label_id)));
+    }
+    /**
+     * Detach label if it is attached
+     * and create an event about it
+     */
+    pub fn detach_label(&mut self, label_id: &str, created_by: String) {
+        if !self.label_ids.iter().any(|l| l == label_id) {
+            return;
+        }
+        self.label_ids.retain(|l| l != label_id);
+        self.events.push(Event::new(
+            created_by,
+            EventKind::LabelDetached(label_id.to_string()),
+        ));
     }
     /**
-     * Remove a given label is if it's in the list
+     * Label migration
+     * Replace legacy labels with the given registry IDs,
+     * without creating events.
      */
-    pub fn remove_label(&mut self, label: Label, created_by: String) {
-        // Check if the label is in the label list
-        if let Some(_) = self.labels.iter().position(|l| *l == label) {
-            // First remove label if match
-            self.labels.retain(|l| *l != label.clone());
-            // Then create an event
-            self.events
-                .push(Event::new(created_by, EventKind::LabelRemoved(label)));
+    pub fn migrate_labels(&mut self, label_ids: Vec<String>) {
+        for label_id in label_ids {
+            if !self.label_ids.contains(&label_id) {
+                self.label_ids.push(label_id);
+            }
         }
+        self.labels = Vec::new();
     }
     /**
      * Get assigned to value
@@ -124,6 +225,8 @@ impl Issue {
         }
         // Set assigned_to value
         self.assigned_to = user.clone();
+        // Assignee follows the issue
+        self.follow(user.clone());
         // Create an event from it
         self.events
             .push(Event::new(created_by, EventKind::AssignedTo(user)));
@@ -134,6 +237,69 @@ impl Issue {
     pub fn get_events(&self) -> Vec<Event> {
         self.events.clone()
     }
+    /**
+     * Get number of events
+     * Use it before a change, to find the
+     * events created by the change later.
+     */
+    pub fn get_event_count(&self) -> usize {
+        self.events.len()
+    }
+    /**
+     * Get events created after the first @from events
+     */
+    pub fn get_events_from(&self, from: usize) -> Vec<(usize, Event)> {
+        self.events
+            .iter()
+            .enumerate()
+            .skip(from)
+            .map(|(index, event)| (index, event.clone()))
+            .collect()
+    }
+    /**
+     * Section tag of the event at the given index
+     * Comments use their comment ID, other events
+     * their position in the event list.
+     */
+    pub fn get_event_section(&self, index: usize) -> Option<String> {
+        self.events.get(index).map(|event| match &event.kind {
+            EventKind::NewComment(comment) => format!("comment-{}", comment.get_id()),
+            _ => format!("event-{}", index),
+        })
+    }
+    /**
+     * Get comments in order of creation
+     */
+    pub fn get_comments(&self) -> Vec<&Comment> {
+        self.events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                EventKind::NewComment(comment) if !comment.is_deleted => Some(comment),
+                _ => None,
+            })
+            .collect()
+    }
+    /**
+     * Date of the last event,
+     * or the creation date if there is no event
+     */
+    pub fn get_date_updated(&self) -> DateTime<Utc> {
+        match self.events.last() {
+            Some(event) => event.date_created,
+            None => self.date_created,
+        }
+    }
+    /**
+     * Task list progress of the description
+     * and the not deleted comments
+     */
+    pub fn get_task_progress(&self) -> TaskProgress {
+        let mut progress = task_progress(&self.description);
+        for comment in self.get_comments() {
+            progress.add(task_progress(comment.get_text()));
+        }
+        progress
+    }
     /**
      * Get comment count: usize
      */
@@ -143,15 +309,141 @@ impl Issue {
     /**
      * Create a comment and then add it
      * as a NewComment event.
+     * Mentioned users start to follow the issue.
+     * @mentions should contain only existing user IDs,
+     * parse them with crate::issue::parse_mentions.
      */
-    pub fn add_comment(&mut self, text: String, created_by: String) {
+    pub fn add_comment(&mut self, text: String, created_by: String, mentions: Vec<String>) {
         // Increase comment_count by one
         self.comment_count += 1;
+        // Mentioned users follow the issue
+        for user_id in &mentions {
+            self.follow(user_id.clone());
+        }
+        let mut comment = Comment::new(self.comment_count, text, created_by.clone());
+        comment.set_mentions(mentions);
         // Push comment to events
+        self.events
+            .push(Event::new(created_by, EventKind::NewComment(comment)));
+    }
+    /**
+     * Edit comment text and keep the previous one
+     * in a CommentEdited event
+     * Only the author or an admin can edit it.
+     * @mentions replace the comment mentions,
+     * and mentioned users start to follow the issue.
+     * Returns the previous text if it has changed.
+     */
+    pub fn edit_comment(
+        &mut self,
+        comment_id: usize,
+        text: String,
+        user_id: String,
+        is_admin: bool,
+        mentions: Vec<String>,
+    ) -> AppResult<Option<String>> {
+        let comment = self.get_editable_comment(comment_id, &user_id, is_admin)?;
+        if comment.text == text {
+            return Ok(None);
+        }
+        let previous = std::mem::replace(&mut comment.text, text);
+        comment.date_edited = Some(Utc::now());
+        comment.set_mentions(mentions.clone());
+        for mentioned in mentions {
+            self.follow(mentioned);
+        }
         self.events.push(Event::new(
-            created_by,
-            EventKind::NewComment(Comment::new(self.comment_count, text)),
+            user_id,
+            EventKind::CommentEdited {
+                comment_id,
+                previous: previous.clone(),
+            },
         ));
+        Ok(Some(previous))
+    }
+    /**
+     * Soft delete comment
+     * Only the author or an admin can delete it.
+     */
+    pub fn delete_comment(
+        &mut self,
+        comment_id: usize,
+        user_id: String,
+        is_admin: bool,
+    ) -> AppResult<()> {
+        let comment = self.get_editable_comment(comment_id, &user_id, is_admin)?;
+        comment.is_deleted = true;
+        self.events.push(Event::new(
+            user_id,
+            EventKind::CommentDeleted { comment_id },
+        ));
+        Ok(())
+    }
+    /**
+     * Find a not deleted comment the user can change
+     */
+    fn get_editable_comment(
+        &mut self,
+        comment_id: usize,
+        user_id: &str,
+        is_admin: bool,
+    ) -> AppResult<&mut Comment> {
+        for event in &mut self.events {
+            let event_author = event.created_by.clone();
+            if let EventKind::NewComment(comment) = &mut event.kind {
+                if comment.get_id() != comment_id || comment.is_deleted {
+                    continue;
+                }
+                // Older comments have no author stored
+                let author = if comment.created_by.is_empty() {
+                    event_author
+                } else {
+                    comment.created_by.clone()
+                };
+                if author != user_id && !is_admin {
+                    return Err(Error::Forbidden(
+                        "Csak a saját hozzászólását módosíthatja".to_owned(),
+                    ));
+                }
+                return Ok(comment);
+            }
+        }
+        Err(Error::BadRequest(
+            "A megadott comment ID nem létezik".to_owned(),
+        ))
+    }
+    /**
+     * Event list without the text of deleted comments
+     * Use it to show events to users.
+     */
+    pub fn get_visible_events(&self) -> Vec<Event> {
+        let deleted = self
+            .events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                EventKind::NewComment(comment) if comment.is_deleted => Some(comment.get_id()),
+                _ => None,
+            })
+            .collect::<Vec<usize>>();
+        self.events
+            .iter()
+            .cloned()
+            .map(|mut event| {
+                match &mut event.kind {
+                    EventKind::NewComment(comment) if comment.is_deleted => {
+                        comment.text = String::new();
+                        comment.mentions = Vec::new();
+                    }
+                    // Edit history of a deleted comment is hidden too
+                    EventKind::CommentEdited {
+                        comment_id,
+                        previous,
+                    } if deleted.contains(comment_id) => *previous = String::new(),
+                    _ => (),
+                }
+                event
+            })
+            .collect()
     }
     /**
      * Looking for comment by a given ID
@@ -210,6 +502,17 @@ impl Issue {
     pub fn get_followed_by(&self) -> Vec<String> {
         self.followed_by.clone()
     }
+    /**
+     * Get followers except the given user
+     * Use it to notify everybody but the actor.
+     */
+    pub fn get_followers_except(&self, user_id: &str) -> Vec<String> {
+        self.followed_by
+            .iter()
+            .filter(|u| *u != user_id)
+            .cloned()
+            .collect()
+    }
     /**
      * Set is_open status to true
      * and create an event about it
@@ -275,4 +578,254 @@ impl Comment {
     pub fn unlike(&mut self, user_id: String) {
         self.liked.retain(|c| *c != user_id);
     }
+    /**
+     * Get comment text
+     */
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+    /**
+     * Get author @userid
+     * Empty for comments created before it was stored
+     */
+    pub fn get_created_by(&self) -> &str {
+        &self.created_by
+    }
+    pub fn get_date_edited(&self) -> Option<DateTime<Utc>> {
+        self.date_edited
+    }
+    pub fn is_deleted(&self) -> bool {
+        self.is_deleted
+    }
+    /**
+     * Get mentioned user IDs
+     */
+    pub fn get_mentions(&self) -> &Vec<String> {
+        &self.mentions
+    }
+    /**
+     * Set mentioned user IDs
+     */
+    pub fn set_mentions(&mut self, mentions: Vec<String>) {
+        self.mentions = mentions;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_followers() {
+        let mut issue = Issue::new("Title".into(), "Description".into(), "creator".into());
+        assert_eq!(issue.get_followed_by(), vec!["creator".to_string()]);
+        issue.set_assigned_to("assignee".into(), "creator".into());
+        assert_eq!(
+            issue.get_followers_except("creator"),
+            vec!["assignee".to_string()]
+        );
+        issue.unfollow("creator".into());
+        assert_eq!(issue.get_followed_by(), vec!["assignee".to_string()]);
+    }
+
+    #[test]
+    fn test_issue_reference() {
+        let mut issue = Issue::new("Title".into(), "Description".into(), "creator".into());
+        assert_eq!(issue.get_number(), 0);
+        issue.set_number(142);
+        assert_eq!(issue.get_number(), 142);
+        issue.add_reference("abc".into(), 7, 2, "peter".into());
+        match &issue.get_events()[0].kind {
+            EventKind::Referenced {
+                id,
+                number,
+                comment_id,
+            } => {
+                assert_eq!(id, "abc");
+                assert_eq!(*number, 7);
+                assert_eq!(*comment_id, 2);
+            }
+            _ => panic!("Referenced event expected"),
+        }
+        assert_eq!(issue.get_event_section(0), Some("event-0".to_string()));
+    }
+
+    #[test]
+    fn test_issue_event_section() {
+        let mut issue = Issue::new("Title".into(), "Description".into(), "creator".into());
+        issue.close_issue("creator".into());
+        let count = issue.get_event_count();
+        issue.add_comment("Hello @anna".into(), "creator".into(), vec!["anna".into()]);
+        let new_events = issue.get_events_from(count);
+        assert_eq!(new_events.len(), 1);
+        assert_eq!(new_events[0].0, 1);
+        assert_eq!(issue.get_event_section(0), Some("event-0".to_string()));
+        assert_eq!(issue.get_event_section(1), Some("comment-1".to_string()));
+        assert_eq!(issue.get_event_section(2), None);
+        // Mentioned user follows the issue
+        assert!(issue.get_followed_by().contains(&"anna".to_string()));
+        match &new_events[0].1.kind {
+            EventKind::NewComment(comment) => {
+                assert_eq!(comment.get_mentions(), &vec!["anna".to_string()])
+            }
+            _ => panic!("NewComment event expected"),
+        }
+    }
+
+    #[test]
+    fn test_issue_labels() {
+        let mut issue = Issue::new("Title".into(), "Description".into(), "creator".into());
+        issue.attach_label("urgent".into(), "peter".into());
+        issue.attach_label("urgent".into(), "peter".into());
+        issue.detach_label("missing", "peter".into());
+        assert_eq!(issue.get_label_ids(), &vec!["urgent".to_string()]);
+        assert_eq!(issue.get_event_count(), 1);
+        issue.detach_label("urgent", "peter".into());
+        assert_eq!(issue.get_label_ids().len(), 0);
+        match &issue.get_events()[1].kind {
+            EventKind::LabelDetached(id) => assert_eq!(id, "urgent"),
+            _ => panic!("LabelDetached event expected"),
+        }
+        issue.labels = vec![Label::new("Bug".into(), "#fff".into(), "red".into())];
+        issue.migrate_labels(vec!["bug".into(), "bug".into()]);
+        assert_eq!(issue.get_labels().len(), 0);
+        assert_eq!(issue.get_label_ids(), &vec!["bug".to_string()]);
+        assert_eq!(issue.get_event_count(), 2);
+    }
+
+    #[test]
+    fn test_issue_edit() {
+        let mut issue = Issue::new("Title".into(), "Description".into(), "creator".into());
+        assert!(issue.edit_title(" ".into(), "creator".into()).is_err());
+        issue
+            .edit_title("New title".into(), "peter".into())
+            .unwrap();
+        issue
+            .edit_title("New title".into(), "peter".into())
+            .unwrap();
+        assert_eq!(
+            issue.edit_description(
+                "New description @anna".into(),
+                "peter".into(),
+                vec!["anna".into()]
+            ),
+            Some("Description".to_string())
+        );
+        assert_eq!(
+            issue.edit_description(
+                "New description @anna".into(),
+                "peter".into(),
+                vec!["anna".into()]
+            ),
+            None
+        );
+        assert_eq!(issue.get_followed_by().contains(&"anna".to_string()), true);
+        assert_eq!(issue.get_title(), "New title");
+        assert_eq!(issue.get_description(), "New description @anna");
+        let events = issue.get_events();
+        assert_eq!(events.len(), 2);
+        match &events[0].kind {
+            EventKind::TitleChanged { previous } => assert_eq!(previous, "Title"),
+            _ => panic!("TitleChanged event expected"),
+        }
+        match &events[1].kind {
+            EventKind::DescriptionChanged { previous } => assert_eq!(previous, "Description"),
+            _ => panic!("DescriptionChanged event expected"),
+        }
+    }
+
+    #[test]
+    fn test_comment_edit() {
+        let mut issue = Issue::new("Title".into(), "Description".into(), "creator".into());
+        issue.add_comment("Helo".into(), "peter".into(), Vec::new());
+        assert_eq!(issue.get_comments()[0].get_created_by(), "peter");
+        // Only the author or an admin
+        match issue.edit_comment(1, "Hello".into(), "anna".into(), false, Vec::new()) {
+            Err(Error::Forbidden(_)) => (),
+            _ => panic!("Forbidden error expected"),
+        }
+        assert_eq!(
+            issue
+                .edit_comment(
+                    1,
+                    "Hello @anna".into(),
+                    "peter".into(),
+                    false,
+                    vec!["anna".into()]
+                )
+                .unwrap(),
+            Some("Helo".to_string())
+        );
+        assert_eq!(
+            issue
+                .edit_comment(
+                    1,
+                    "Hello @anna".into(),
+                    "peter".into(),
+                    false,
+                    vec!["anna".into()]
+                )
+                .unwrap(),
+            None
+        );
+        assert_eq!(issue.get_comments()[0].get_text(), "Hello @anna");
+        assert_eq!(
+            issue.get_comments()[0].get_mentions(),
+            &vec!["anna".to_string()]
+        );
+        assert_eq!(issue.get_followed_by().contains(&"anna".to_string()), true);
+        assert!(issue.get_comments()[0].get_date_edited().is_some());
+        match &issue.get_events()[1].kind {
+            EventKind::CommentEdited {
+                comment_id,
+                previous,
+            } => {
+                assert_eq!(*comment_id, 1);
+                assert_eq!(previous, "Helo");
+            }
+            _ => panic!("CommentEdited event expected"),
+        }
+        assert!(issue
+            .edit_comment(2, "Hi".into(), "peter".into(), true, Vec::new())
+            .is_err());
+        // Admin can delete it
+        issue.delete_comment(1, "admin".into(), true).unwrap();
+        assert_eq!(issue.get_comments().len(), 0);
+        assert!(issue
+            .edit_comment(1, "Again".into(), "peter".into(), false, Vec::new())
+            .is_err());
+        match &issue.get_visible_events()[0].kind {
+            EventKind::NewComment(comment) => {
+                assert!(comment.is_deleted());
+                assert_eq!(comment.get_text(), "");
+            }
+            _ => panic!("NewComment event expected"),
+        }
+        match &issue.get_visible_events()[1].kind {
+            EventKind::CommentEdited { previous, .. } => assert_eq!(previous, ""),
+            _ => panic!("CommentEdited event expected"),
+        }
+        // Stored history is kept
+        match &issue.get_events()[1].kind {
+            EventKind::CommentEdited { previous, .. } => assert_eq!(previous, "Helo"),
+            _ => panic!("CommentEdited event expected"),
+        }
+        assert_eq!(issue.get_comment_count(), 1);
+    }
+
+    #[test]
+    fn test_issue_task_progress() {
+        let mut issue = Issue::new(
+            "Title".into(),
+            "- [x] One\n- [ ] Two".into(),
+            "creator".into(),
+        );
+        issue.add_comment("- [ ] Three".into(), "peter".into(), Vec::new());
+        issue.add_comment("- [x] Deleted".into(), "peter".into(), Vec::new());
+        issue.delete_comment(2, "peter".into(), false).unwrap();
+        assert_eq!(
+            issue.get_task_progress(),
+            TaskProgress { done: 1, total: 3 }
+        );
+    }
 }
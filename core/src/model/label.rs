@@ -0,0 +1,302 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error::*;
+use crate::login::generate_token_id;
+pub use crate::model::version::label::v1::IssueLabel;
+use crate::prelude::*;
+use chrono::prelude::*;
+
+// Max label subject length
+const SUBJECT_MAX_CHARS: usize = 30;
+// Colors of migrated labels with invalid colors
+pub const DEFAULT_TEXT_COLOR: &str = "#000000";
+pub const DEFAULT_BACKGROUND_COLOR: &str = "#e0e0e0";
+
+// CSS named colors
+const NAMED_COLORS: &[&str] = &[
+    "aliceblue",
+    "antiquewhite",
+    "aqua",
+    "aquamarine",
+    "azure",
+    "beige",
+    "bisque",
+    "black",
+    "blanchedalmond",
+    "blue",
+    "blueviolet",
+    "brown",
+    "burlywood",
+    "cadetblue",
+    "chartreuse",
+    "chocolate",
+    "coral",
+    "cornflowerblue",
+    "cornsilk",
+    "crimson",
+    "cyan",
+    "darkblue",
+    "darkcyan",
+    "darkgoldenrod",
+    "darkgray",
+    "darkgreen",
+    "darkgrey",
+    "darkkhaki",
+    "darkmagenta",
+    "darkolivegreen",
+    "darkorange",
+    "darkorchid",
+    "darkred",
+    "darksalmon",
+    "darkseagreen",
+    "darkslateblue",
+    "darkslategray",
+    "darkslategrey",
+    "darkturquoise",
+    "darkviolet",
+    "deeppink",
+    "deepskyblue",
+    "dimgray",
+    "dimgrey",
+    "dodgerblue",
+    "firebrick",
+    "floralwhite",
+    "forestgreen",
+    "fuchsia",
+    "gainsboro",
+    "ghostwhite",
+    "gold",
+    "goldenrod",
+    "gray",
+    "green",
+    "greenyellow",
+    "grey",
+    "honeydew",
+    "hotpink",
+    "indianred",
+    "indigo",
+    "ivory",
+    "khaki",
+    "lavender",
+    "lavenderblush",
+    "lawngreen",
+    "lemonchiffon",
+    "lightblue",
+    "lightcoral",
+    "lightcyan",
+    "lightgoldenrodyellow",
+    "lightgray",
+    "lightgreen",
+    "lightgrey",
+    "lightpink",
+    "lightsalmon",
+    "lightseagreen",
+    "lightskyblue",
+    "lightslategray",
+    "lightslategrey",
+    "lightsteelblue",
+    "lightyellow",
+    "lime",
+    "limegreen",
+    "linen",
+    "magenta",
+    "maroon",
+    "mediumaquamarine",
+    "mediumblue",
+    "mediumorchid",
+    "mediumpurple",
+    "mediumseagreen",
+    "mediumslateblue",
+    "mediumspringgreen",
+    "mediumturquoise",
+    "mediumvioletred",
+    "midnightblue",
+    "mintcream",
+    "mistyrose",
+    "moccasin",
+    "navajowhite",
+    "navy",
+    "oldlace",
+    "olive",
+    "olivedrab",
+    "orange",
+    "orangered",
+    "orchid",
+    "palegoldenrod",
+    "palegreen",
+    "paleturquoise",
+    "palevioletred",
+    "papayawhip",
+    "peachpuff",
+    "peru",
+    "pink",
+    "plum",
+    "powderblue",
+    "purple",
+    "rebeccapurple",
+    "red",
+    "rosybrown",
+    "royalblue",
+    "saddlebrown",
+    "salmon",
+    "sandybrown",
+    "seagreen",
+    "seashell",
+    "sienna",
+    "silver",
+    "skyblue",
+    "slateblue",
+    "slategray",
+    "slategrey",
+    "snow",
+    "springgreen",
+    "steelblue",
+    "tan",
+    "teal",
+    "thistle",
+    "tomato",
+    "turquoise",
+    "violet",
+    "wheat",
+    "white",
+    "whitesmoke",
+    "yellow",
+    "yellowgreen",
+];
+
+/// Validate CSS color
+/// Accepts #rgb, #rgba, #rrggbb, #rrggbbaa hex colors
+/// and CSS named colors, case insensitive.
+/// Returns the normalized, lowercase color.
+pub fn validate_color(color: &str) -> AppResult<String> {
+    let color = color.trim().to_lowercase();
+    let is_valid = match color.strip_prefix('#') {
+        Some(hex) => {
+            [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => NAMED_COLORS.contains(&color.as_str()),
+    };
+    if is_valid {
+        Ok(color)
+    } else {
+        Err(BadRequest(format!("Érvénytelen szín: {}", color)))
+    }
+}
+
+fn validate_subject(subject: &str) -> AppResult<String> {
+    let subject = subject.trim().to_string();
+    if subject.is_empty() || subject.chars().count() > SUBJECT_MAX_CHARS {
+        return Err(BadRequest(format!(
+            "A címke neve minimum 1 és maximum {} karakter lehet",
+            SUBJECT_MAX_CHARS
+        )));
+    }
+    Ok(subject)
+}
+
+impl IssueLabel {
+    pub fn new(
+        subject: &str,
+        text_color: &str,
+        background_color: &str,
+        created_by: &str,
+    ) -> AppResult<Self> {
+        Ok(IssueLabel {
+            id: generate_token_id(),
+            subject: validate_subject(subject)?,
+            text_color: validate_color(text_color)?,
+            background_color: validate_color(background_color)?,
+            date_created: Utc::now(),
+            created_by: created_by.to_string(),
+            is_deleted: false,
+        })
+    }
+    pub fn get_subject(&self) -> &str {
+        &self.subject
+    }
+    /**
+     * True if the subject matches, case insensitive
+     */
+    pub fn has_subject(&self, subject: &str) -> bool {
+        self.subject.to_lowercase() == subject.trim().to_lowercase()
+    }
+    pub fn get_text_color(&self) -> &str {
+        &self.text_color
+    }
+    pub fn get_background_color(&self) -> &str {
+        &self.background_color
+    }
+    pub fn get_date_created(&self) -> DateTime<Utc> {
+        self.date_created
+    }
+    pub fn get_created_by(&self) -> &str {
+        &self.created_by
+    }
+    pub fn is_deleted(&self) -> bool {
+        self.is_deleted
+    }
+    pub fn rename(&mut self, subject: &str) -> AppResult<()> {
+        self.subject = validate_subject(subject)?;
+        Ok(())
+    }
+    pub fn recolor(&mut self, text_color: &str, background_color: &str) -> AppResult<()> {
+        let text_color = validate_color(text_color)?;
+        let background_color = validate_color(background_color)?;
+        self.text_color = text_color;
+        self.background_color = background_color;
+        Ok(())
+    }
+    pub fn delete(&mut self) {
+        self.is_deleted = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_color() {
+        assert_eq!(validate_color("#FFF").unwrap(), "#fff");
+        assert_eq!(validate_color(" #00ff0080 ").unwrap(), "#00ff0080");
+        assert_eq!(validate_color("White").unwrap(), "white");
+        assert!(validate_color("#ff").is_err());
+        assert!(validate_color("#gggggg").is_err());
+        assert!(validate_color("notacolor").is_err());
+        assert!(validate_color("red;background:url(x)").is_err());
+    }
+
+    #[test]
+    fn test_label() {
+        assert!(IssueLabel::new(" ", "#000", "#fff", "admin").is_err());
+        assert!(IssueLabel::new("Urgent", "#000", "bad", "admin").is_err());
+        let mut label = IssueLabel::new(" Urgent ", "#000", "RED", "admin").unwrap();
+        assert_eq!(label.get_subject(), "Urgent");
+        assert_eq!(label.get_background_color(), "red");
+        assert!(label.has_subject("urgent"));
+        label.rename("Very urgent").unwrap();
+        assert!(label.rename("").is_err());
+        assert_eq!(label.get_subject(), "Very urgent");
+        assert!(label.recolor("#fff", "nope").is_err());
+        assert_eq!(label.get_text_color(), "#000");
+        label.recolor("#fff", "#000").unwrap();
+        assert_eq!(label.get_text_color(), "#fff");
+        label.delete();
+        assert!(label.is_deleted());
+    }
+}
@@ -16,18 +16,26 @@
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
 // pub mod cart;
+pub mod counter;
 pub mod customer;
 pub mod issue;
+pub mod label;
 pub mod notification;
+pub mod outbox;
 // pub mod product;
+pub mod session;
 // pub mod stock;
 pub mod user;
 pub mod version;
 
 // pub use cart::*;
+pub use counter::*;
 pub use customer::Customer;
 pub use issue::*;
+pub use label::*;
 pub use notification::*;
+pub use outbox::*;
 // pub use product::*;
+pub use session::*;
 // pub use stock::*;
 pub use user::*;
@@ -15,6 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::check::check_link;
+use crate::config::number_from_env;
 use crate::error::Error;
 use crate::prelude::AppResult;
 use chrono::prelude::*;
@@ -22,7 +24,7 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use storaget::*;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Location {
     /**
      * When we have no location provided
@@ -39,6 +41,26 @@ pub enum Location {
      * @section: String => Section tag #
      */
     Issue { id: String, section: Option<String> },
+    /**
+     * Customer location
+     * @id: String => customer ID
+     */
+    Customer { id: String },
+    /**
+     * User location
+     * @id: String => user ID
+     */
+    User { id: String },
+    /**
+     * Product location
+     * @id: String => product SKU
+     */
+    Product { id: String },
+    /**
+     * Order location
+     * @id: String => order ID
+     */
+    Order { id: String },
 }
 
 impl Location {
@@ -57,10 +79,386 @@ impl Location {
                     None => "".to_owned(),
                 }
             ),
+            Location::Customer { id } => format!("/customer/{}", id),
+            Location::User { id } => format!("/user/{}", id),
+            Location::Product { id } => format!("/product/{}", id),
+            Location::Order { id } => format!("/order/{}", id),
+        }
+    }
+    /**
+     * True if both point to the same entity
+     * Issue sections are ignored.
+     */
+    pub fn is_same_target(&self, other: &Location) -> bool {
+        match (self, other) {
+            (Location::Issue { id: a, .. }, Location::Issue { id: b, .. }) => a == b,
+            (a, b) => a == b,
+        }
+    }
+    /**
+     * Raw links must be relative paths or http(s) URLs
+     */
+    pub fn validate(&self) -> AppResult<()> {
+        match self {
+            Location::Raw(url) => check_link(url),
+            _ => Ok(()),
+        }
+    }
+}
+
+/**
+ * User who did the action
+ * Name is stored, so it can be rendered
+ * without loading the user.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Actor {
+    pub id: String,
+    pub name: String,
+}
+
+impl Actor {
+    pub fn new(id: &str, name: &str) -> Self {
+        Actor {
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+}
+
+/**
+ * Typed notification kind
+ * Contains the action and its parameters, the target
+ * entity is the notification Location. Clients can render,
+ * group and translate notifications by it. Subject is still
+ * rendered from it for emails and older clients.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationKind {
+    /**
+     * Plain text, only the subject is known
+     * Notifications created before typed kinds
+     */
+    #[default]
+    Text,
+    /**
+     * Message sent to every user by an admin
+     */
+    Broadcast { actor: Actor, message: String },
+    /**
+     * Issue assigned to the user
+     */
+    IssueAssigned { actor: Actor, title: String },
+    /**
+     * User mentioned in an issue description or comment
+     */
+    IssueMentioned { actor: Actor, title: String },
+    /**
+     * New comment on a followed issue
+     */
+    IssueCommented { actor: Actor, title: String },
+    /**
+     * Label added to a followed issue
+     */
+    IssueLabelAdded {
+        actor: Actor,
+        title: String,
+        label: String,
+    },
+    /**
+     * Label removed from a followed issue
+     */
+    IssueLabelRemoved {
+        actor: Actor,
+        title: String,
+        label: String,
+    },
+    /**
+     * Followed issue assigned to another user
+     */
+    IssueReassigned {
+        actor: Actor,
+        title: String,
+        assigned_to: String,
+    },
+    /**
+     * Followed issue closed
+     */
+    IssueClosed { actor: Actor, title: String },
+    /**
+     * Followed issue re-opened
+     */
+    IssueOpened { actor: Actor, title: String },
+}
+
+impl NotificationKind {
+    /**
+     * Delivery category of the kind
+     */
+    pub fn get_category(&self) -> NotificationCategory {
+        match self {
+            NotificationKind::IssueAssigned { .. } => NotificationCategory::Assignment,
+            NotificationKind::IssueMentioned { .. } => NotificationCategory::Mention,
+            NotificationKind::IssueCommented { .. } => NotificationCategory::Comment,
+            _ => NotificationCategory::System,
+        }
+    }
+    /**
+     * Get actor, if the kind has one
+     */
+    pub fn get_actor(&self) -> Option<&Actor> {
+        match self {
+            NotificationKind::Text => None,
+            NotificationKind::Broadcast { actor, .. }
+            | NotificationKind::IssueAssigned { actor, .. }
+            | NotificationKind::IssueMentioned { actor, .. }
+            | NotificationKind::IssueCommented { actor, .. }
+            | NotificationKind::IssueLabelAdded { actor, .. }
+            | NotificationKind::IssueLabelRemoved { actor, .. }
+            | NotificationKind::IssueReassigned { actor, .. }
+            | NotificationKind::IssueClosed { actor, .. }
+            | NotificationKind::IssueOpened { actor, .. } => Some(actor),
+        }
+    }
+    /**
+     * Unseen notifications of the same kind and
+     * target are collapsed into one, except plain texts
+     * and broadcasts, as they have their own message.
+     */
+    pub fn is_groupable(&self) -> bool {
+        !matches!(
+            self,
+            NotificationKind::Text | NotificationKind::Broadcast { .. }
+        )
+    }
+    /**
+     * True if both are the same kind
+     * Parameters are not compared.
+     */
+    pub fn is_same_kind(&self, other: &NotificationKind) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+    /**
+     * Render subject text of a group
+     * @count: number of collapsed notifications
+     */
+    pub fn render_group_subject(&self, count: usize, actors: &[Actor]) -> String {
+        if count < 2 {
+            return self.render_subject();
+        }
+        let names = actors
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        match self {
+            NotificationKind::IssueCommented { title, .. } => format!(
+                "{} új hozzászólás a következő issue-hoz: {} ({})",
+                count, title, names
+            ),
+            NotificationKind::IssueMentioned { title, .. } => format!(
+                "{} új említés a következő issue-ban: {} ({})",
+                count, title, names
+            ),
+            NotificationKind::IssueAssigned { title, .. }
+            | NotificationKind::IssueLabelAdded { title, .. }
+            | NotificationKind::IssueLabelRemoved { title, .. }
+            | NotificationKind::IssueReassigned { title, .. }
+            | NotificationKind::IssueClosed { title, .. }
+            | NotificationKind::IssueOpened { title, .. } => format!(
+                "{} új esemény a következő issue-n: {} ({})",
+                count, title, names
+            ),
+            _ => self.render_subject(),
+        }
+    }
+    /**
+     * Render subject text
+     */
+    pub fn render_subject(&self) -> String {
+        match self {
+            NotificationKind::Text => "".to_owned(),
+            NotificationKind::Broadcast { message, .. } => message.to_owned(),
+            NotificationKind::IssueAssigned { actor, title } => format!(
+                "{} hozzárendelte Önt a következő issue-hoz: {}",
+                actor.name, title
+            ),
+            NotificationKind::IssueMentioned { actor, title } => format!(
+                "{} megemlítette Önt a következő issue-ban: {}",
+                actor.name, title
+            ),
+            NotificationKind::IssueCommented { actor, title } => {
+                format!("{} hozzászólt a következő issue-hoz: {}", actor.name, title)
+            }
+            NotificationKind::IssueLabelAdded {
+                actor,
+                title,
+                label,
+            } => format!(
+                "{} hozzáadta a(z) {} címkét a következő issue-hoz: {}",
+                actor.name, label, title
+            ),
+            NotificationKind::IssueLabelRemoved {
+                actor,
+                title,
+                label,
+            } => format!(
+                "{} eltávolította a(z) {} címkét a következő issue-ról: {}",
+                actor.name, label, title
+            ),
+            NotificationKind::IssueReassigned { actor, title, .. } => {
+                format!("{} átrendelte a következő issue-t: {}", actor.name, title)
+            }
+            NotificationKind::IssueClosed { actor, title } => {
+                format!("{} lezárta a következő issue-t: {}", actor.name, title)
+            }
+            NotificationKind::IssueOpened { actor, title } => {
+                format!("{} újranyitotta a következő issue-t: {}", actor.name, title)
+            }
+        }
+    }
+}
+
+/**
+ * Notification category
+ * Users can set the delivery mode per category
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationCategory {
+    /**
+     * Issue assigned to the user
+     */
+    Assignment,
+    /**
+     * User mentioned with @userid
+     */
+    Mention,
+    /**
+     * Comment on a followed issue
+     */
+    Comment,
+    /**
+     * System messages
+     */
+    #[default]
+    System,
+}
+
+/**
+ * How a user receives notifications
+ * of a given category
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryMode {
+    /**
+     * Only inside the app
+     */
+    InApp,
+    /**
+     * In app, and email right away
+     */
+    Immediate,
+    /**
+     * In app, and unseen ones in a daily email
+     */
+    DailyDigest,
+}
+
+/**
+ * Delivery mode per notification category
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NotificationPreferences {
+    pub assignment: DeliveryMode,
+    pub mention: DeliveryMode,
+    pub comment: DeliveryMode,
+    pub system: DeliveryMode,
+}
+
+impl Default for NotificationPreferences {
+    fn default() -> Self {
+        NotificationPreferences {
+            assignment: DeliveryMode::Immediate,
+            mention: DeliveryMode::Immediate,
+            comment: DeliveryMode::InApp,
+            system: DeliveryMode::InApp,
         }
     }
 }
 
+impl NotificationPreferences {
+    pub fn get(&self, category: NotificationCategory) -> DeliveryMode {
+        match category {
+            NotificationCategory::Assignment => self.assignment,
+            NotificationCategory::Mention => self.mention,
+            NotificationCategory::Comment => self.comment,
+            NotificationCategory::System => self.system,
+        }
+    }
+    pub fn set(&mut self, category: NotificationCategory, mode: DeliveryMode) {
+        match category {
+            NotificationCategory::Assignment => self.assignment = mode,
+            NotificationCategory::Mention => self.mention = mode,
+            NotificationCategory::Comment => self.comment = mode,
+            NotificationCategory::System => self.system = mode,
+        }
+    }
+    /**
+     * True if any category is sent in daily digest
+     */
+    pub fn has_digest(&self) -> bool {
+        [self.assignment, self.mention, self.comment, self.system]
+            .contains(&DeliveryMode::DailyDigest)
+    }
+}
+
+/**
+ * Retention policy of seen notifications
+ * Unseen notifications are never pruned.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    /**
+     * Seen notifications older than this are removed
+     */
+    pub max_age_days: i64,
+    /**
+     * Max number of seen notifications kept
+     * the oldest ones are removed above it
+     */
+    pub max_seen: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            max_age_days: 90,
+            max_seen: 500,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Load policy from ENV
+    /// NOTIFICATION_RETENTION_DAYS and NOTIFICATION_MAX_SEEN
+    /// are optional, missing values use the default.
+    /// Retention days must be at least 1.
+    pub fn from_env() -> AppResult<Self> {
+        let default = RetentionPolicy::default();
+        Ok(RetentionPolicy {
+            max_age_days: number_from_env(
+                "NOTIFICATION_RETENTION_DAYS",
+                default.max_age_days,
+                1..,
+            )?,
+            max_seen: number_from_env("NOTIFICATION_MAX_SEEN", default.max_seen, 0..)?,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NotificationContainer {
     /**
@@ -86,6 +484,12 @@ pub struct NotificationContainer {
      * TODO: Verify it
      */
     notifications: Vec<Notification>,
+    /**
+     * Date of the last daily digest email
+     * Next digest contains notifications after this date
+     */
+    #[serde(default)]
+    last_digest: Option<DateTime<Utc>>,
 }
 
 // Implement StorageObject for NotificationContainer
@@ -102,6 +506,7 @@ impl NotificationContainer {
             id,
             notification_counter: 0,
             notifications: Vec::new(),
+            last_digest: None,
         }
     }
     /**
@@ -152,16 +557,190 @@ impl NotificationContainer {
             None => None,
         }
     }
+    /**
+     * Find notification by id
+     */
+    pub fn find_by_id(&self, id: usize) -> Option<&Notification> {
+        self.notifications.iter().find(|x| x.get_id() == id)
+    }
+    /**
+     * Number of unseen notifications
+     */
+    pub fn get_unread_count(&self) -> usize {
+        self.notifications.iter().filter(|n| n.get_is_new()).count()
+    }
+    /**
+     * Get a page of notifications, newest first
+     * @before: cursor, only notifications with lower ID are returned
+     * @unread_only: skip seen notifications
+     * Use the ID of the last returned notification
+     * as the cursor of the next page.
+     */
+    pub fn get_page(
+        &self,
+        before: Option<usize>,
+        limit: usize,
+        unread_only: bool,
+    ) -> Vec<&Notification> {
+        self.notifications
+            .iter()
+            .rev()
+            .filter(|n| match before {
+                Some(before) => n.get_id() < before,
+                None => true,
+            })
+            .filter(|n| !unread_only || n.get_is_new())
+            .take(limit)
+            .collect()
+    }
+    /**
+     * Set all notifications seen
+     * Returns the IDs of the notifications changed
+     */
+    pub fn set_all_seen(&mut self) -> Vec<usize> {
+        self.notifications
+            .iter_mut()
+            .filter(|n| n.get_is_new())
+            .map(|n| {
+                n.set_seen();
+                n.get_id()
+            })
+            .collect()
+    }
+    /**
+     * Remove all seen notifications
+     * Returns the IDs of the removed notifications
+     */
+    pub fn remove_seen(&mut self) -> Vec<usize> {
+        let removed = self
+            .notifications
+            .iter()
+            .filter(|n| !n.get_is_new())
+            .map(|n| n.get_id())
+            .collect();
+        self.notifications.retain(|n| n.get_is_new());
+        removed
+    }
+    /**
+     * True if prune would remove any notification
+     */
+    pub fn needs_prune(&self, policy: &RetentionPolicy, now: DateTime<Utc>) -> bool {
+        let limit = now - chrono::Duration::days(policy.max_age_days);
+        let seen = self.notifications.iter().filter(|n| !n.get_is_new());
+        seen.clone().count() > policy.max_seen
+            || seen.clone().any(|n| n.get_date_created() <= limit)
+    }
+    /**
+     * Prune seen notifications by the retention policy
+     * Returns the number of removed notifications
+     */
+    pub fn prune(&mut self, policy: &RetentionPolicy, now: DateTime<Utc>) -> usize {
+        let count = self.notifications.len();
+        let limit = now - chrono::Duration::days(policy.max_age_days);
+        self.notifications
+            .retain(|n| n.get_is_new() || n.get_date_created() > limit);
+        // Keep only the newest max_seen seen notifications
+        let mut seen = self
+            .notifications
+            .iter()
+            .filter(|n| !n.get_is_new())
+            .count();
+        if seen > policy.max_seen {
+            self.notifications.retain(|n| {
+                if !n.get_is_new() && seen > policy.max_seen {
+                    seen -= 1;
+                    return false;
+                }
+                true
+            });
+        }
+        count - self.notifications.len()
+    }
+    /**
+     * Find the unseen notification the given one
+     * would be collapsed into by add
+     */
+    pub fn find_group(&self, notification: &Notification) -> Option<&Notification> {
+        if !notification.kind.is_groupable() {
+            return None;
+        }
+        self.notifications.iter().find(|n| {
+            n.is_new
+                && n.kind.is_same_kind(&notification.kind)
+                && match (&n.location, &notification.location) {
+                    (Some(a), Some(b)) => a.is_same_target(b),
+                    _ => false,
+                }
+        })
+    }
     /**
      * Add new notification to notification container
+     * An unseen notification of the same kind and target
+     * is collapsed into the new one: it is removed, and its
+     * count and actors are added to the new notification.
+     * Returns the new notification ID
      */
-    pub fn add(&mut self, notification: Notification) {
+    pub fn add(&mut self, notification: Notification) -> usize {
         // Increment counter
         self.notification_counter += 1;
         // Create new notification
         let mut note = notification;
         note.id = self.notification_counter;
+        if let Some(group_id) = self.find_group(&note).map(|n| n.get_id()) {
+            if let Some(index) = self.notifications.iter().position(|n| n.id == group_id) {
+                let group = self.notifications.remove(index);
+                note.merge(group);
+            }
+        }
         self.notifications.push(note);
+        self.notification_counter
+    }
+    /**
+     * Daily digest is due, if we have not sent it
+     * since the last digest time (given hour of day, UTC)
+     * Never due before the first digest period is started.
+     */
+    pub fn is_digest_due(&self, now: DateTime<Utc>, hour: u32) -> bool {
+        let today = now.date().and_hms(hour, 0, 0);
+        let digest_time = if now >= today {
+            today
+        } else {
+            today - chrono::Duration::days(1)
+        };
+        match self.last_digest {
+            Some(last) => last < digest_time,
+            None => false,
+        }
+    }
+    /**
+     * Start the digest period now
+     * Call it when the user opts into the daily digest,
+     * so the first digest comes at the next digest hour.
+     */
+    pub fn start_digest(&mut self, now: DateTime<Utc>) {
+        self.last_digest = Some(now);
+    }
+    pub fn has_digest_started(&self) -> bool {
+        self.last_digest.is_some()
+    }
+    /**
+     * Notifications for the daily digest
+     * Unseen ones created since the last digest,
+     * in categories the user gets in digest.
+     */
+    pub fn get_digest(&self, preferences: &NotificationPreferences) -> Vec<&Notification> {
+        self.notifications
+            .iter()
+            .filter(|n| n.get_is_new())
+            .filter(|n| match self.last_digest {
+                Some(last) => n.get_date_created() > last,
+                None => true,
+            })
+            .filter(|n| preferences.get(n.get_category()) == DeliveryMode::DailyDigest)
+            .collect()
+    }
+    pub fn set_digest_sent(&mut self, now: DateTime<Utc>) {
+        self.last_digest = Some(now);
     }
 }
 
@@ -191,6 +770,32 @@ pub struct Notification {
      * Type?
      */
     location: Option<Location>,
+    /**
+     * Category, used to find out how
+     * to deliver it to the user
+     */
+    #[serde(default)]
+    category: NotificationCategory,
+    /**
+     * Typed kind with the action parameters
+     */
+    #[serde(default)]
+    kind: NotificationKind,
+    /**
+     * Number of collapsed notifications
+     */
+    #[serde(default = "default_count")]
+    count: usize,
+    /**
+     * Actors of the collapsed notifications
+     * in order of their first action
+     */
+    #[serde(default)]
+    actors: Vec<Actor>,
+}
+
+fn default_count() -> usize {
+    1
 }
 
 impl Notification {
@@ -201,11 +806,68 @@ impl Notification {
             is_new: true,
             subject,
             location: None,
+            category: NotificationCategory::default(),
+            kind: NotificationKind::default(),
+            count: 1,
+            actors: Vec::new(),
+        }
+    }
+    /**
+     * New notification from a typed kind
+     * Subject and category are set by the kind.
+     */
+    pub fn from_kind(kind: NotificationKind, location: Location) -> Self {
+        Notification {
+            id: 0,
+            date_created: Utc::now(),
+            is_new: true,
+            subject: kind.render_subject(),
+            location: Some(location),
+            category: kind.get_category(),
+            actors: kind.get_actor().cloned().into_iter().collect(),
+            count: 1,
+            kind,
         }
     }
+    /**
+     * Collapse an older notification into this one
+     */
+    fn merge(&mut self, older: Notification) {
+        self.count += older.count;
+        let mut actors = older.actors;
+        for actor in self.actors.drain(..) {
+            if !actors.contains(&actor) {
+                actors.push(actor);
+            }
+        }
+        self.actors = actors;
+        self.subject = self.kind.render_group_subject(self.count, &self.actors);
+    }
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+    pub fn get_actors(&self) -> &Vec<Actor> {
+        &self.actors
+    }
+    pub fn get_kind(&self) -> &NotificationKind {
+        &self.kind
+    }
+    /**
+     * Get location data
+     * Use get_location to get it as URL
+     */
+    pub fn get_target(&self) -> Option<&Location> {
+        self.location.as_ref()
+    }
     pub fn set_location(&mut self, location: Location) {
         self.location = Some(location);
     }
+    pub fn set_category(&mut self, category: NotificationCategory) {
+        self.category = category;
+    }
+    pub fn get_category(&self) -> NotificationCategory {
+        self.category
+    }
     /**
      * Set seen to false
      */
@@ -237,3 +899,267 @@ impl Notification {
         &self.subject
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_preferences() {
+        let mut preferences = NotificationPreferences::default();
+        assert_eq!(
+            preferences.get(NotificationCategory::Assignment),
+            DeliveryMode::Immediate
+        );
+        assert_eq!(preferences.has_digest(), false);
+        preferences.set(NotificationCategory::Comment, DeliveryMode::DailyDigest);
+        assert_eq!(
+            preferences.get(NotificationCategory::Comment),
+            DeliveryMode::DailyDigest
+        );
+        assert_eq!(preferences.has_digest(), true);
+    }
+
+    #[test]
+    fn test_digest_due() {
+        let mut container = NotificationContainer::new("demo".into());
+        let morning = Utc.ymd(2020, 3, 1).and_hms(6, 0, 0);
+        // Not started yet
+        assert_eq!(container.is_digest_due(morning, 7), false);
+        assert_eq!(
+            container.is_digest_due(morning + Duration::days(2), 7),
+            false
+        );
+        container.start_digest(morning);
+        assert_eq!(container.has_digest_started(), true);
+        // Next digest time is 7 o'clock
+        assert_eq!(
+            container.is_digest_due(morning + Duration::minutes(30), 7),
+            false
+        );
+        assert_eq!(
+            container.is_digest_due(morning + Duration::hours(1), 7),
+            true
+        );
+        container.set_digest_sent(morning + Duration::hours(1));
+        assert_eq!(
+            container.is_digest_due(morning + Duration::hours(12), 7),
+            false
+        );
+        assert_eq!(
+            container.is_digest_due(morning + Duration::hours(25), 7),
+            true
+        );
+    }
+
+    #[test]
+    fn test_digest() {
+        let mut preferences = NotificationPreferences::default();
+        preferences.set(NotificationCategory::Comment, DeliveryMode::DailyDigest);
+        let mut container = NotificationContainer::new("demo".into());
+        let mut comment = Notification::new("Comment".into());
+        comment.set_category(NotificationCategory::Comment);
+        container.add(comment.clone());
+        container.add(comment);
+        container.add(Notification::new("System".into()));
+        assert_eq!(container.get_digest(&preferences).len(), 2);
+        assert_eq!(container.get_unread_count(), 3);
+        container.get_by_id(1).unwrap().set_seen();
+        assert_eq!(container.get_unread_count(), 2);
+        assert_eq!(container.find_by_id(3).unwrap().get_subject(), "System");
+        assert_eq!(container.get_digest(&preferences).len(), 1);
+        container.set_digest_sent(Utc::now() + Duration::seconds(1));
+        assert_eq!(container.get_digest(&preferences).len(), 0);
+    }
+
+    #[test]
+    fn test_page() {
+        let mut container = NotificationContainer::new("demo".into());
+        for i in 0..5 {
+            container.add(Notification::new(format!("Notification {}", i)));
+        }
+        container.get_by_id(4).unwrap().set_seen();
+        let ids = |page: Vec<&Notification>| page.iter().map(|n| n.get_id()).collect::<Vec<_>>();
+        assert_eq!(ids(container.get_page(None, 2, false)), vec![5, 4]);
+        assert_eq!(ids(container.get_page(Some(4), 2, false)), vec![3, 2]);
+        assert_eq!(ids(container.get_page(Some(2), 2, false)), vec![1]);
+        assert_eq!(ids(container.get_page(None, 2, true)), vec![5, 3]);
+    }
+
+    #[test]
+    fn test_bulk_seen_and_remove() {
+        let mut container = NotificationContainer::new("demo".into());
+        for i in 0..3 {
+            container.add(Notification::new(format!("Notification {}", i)));
+        }
+        container.get_by_id(2).unwrap().set_seen();
+        assert_eq!(container.remove_seen(), vec![2]);
+        assert_eq!(container.set_all_seen(), vec![1, 3]);
+        assert_eq!(container.get_unread_count(), 0);
+        assert_eq!(container.set_all_seen(), Vec::<usize>::new());
+        assert_eq!(container.remove_seen(), vec![1, 3]);
+        assert_eq!(container.get_notifications().len(), 0);
+    }
+
+    #[test]
+    fn test_prune() {
+        let policy = RetentionPolicy {
+            max_age_days: 30,
+            max_seen: 2,
+        };
+        let mut container = NotificationContainer::new("demo".into());
+        for i in 0..5 {
+            container.add(Notification::new(format!("Notification {}", i)));
+        }
+        // Nothing is seen, nothing to prune
+        assert_eq!(
+            container.needs_prune(&policy, Utc::now() + Duration::days(60)),
+            false
+        );
+        assert_eq!(container.prune(&policy, Utc::now() + Duration::days(60)), 0);
+        container.set_all_seen();
+        container.add(Notification::new("Unseen".into()));
+        assert_eq!(container.needs_prune(&policy, Utc::now()), true);
+        // Keep the 2 newest seen ones
+        assert_eq!(container.prune(&policy, Utc::now()), 3);
+        let ids = container
+            .get_notifications()
+            .iter()
+            .map(|n| n.get_id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![4, 5, 6]);
+        assert_eq!(container.needs_prune(&policy, Utc::now()), false);
+        // Old seen ones are removed
+        assert_eq!(container.prune(&policy, Utc::now() + Duration::days(31)), 2);
+        assert_eq!(container.get_notifications().len(), 1);
+    }
+
+    #[test]
+    fn test_kind() {
+        let kind = NotificationKind::IssueCommented {
+            actor: Actor::new("peter", "Peter"),
+            title: "Broken pump".into(),
+        };
+        let notification = Notification::from_kind(
+            kind.clone(),
+            Location::Issue {
+                id: "abc".into(),
+                section: Some("comment-2".into()),
+            },
+        );
+        assert_eq!(notification.get_category(), NotificationCategory::Comment);
+        assert_eq!(
+            notification.get_subject(),
+            "Peter hozzászólt a következő issue-hoz: Broken pump"
+        );
+        assert_eq!(notification.get_kind(), &kind);
+        assert_eq!(kind.get_actor().unwrap().id, "peter");
+        assert_eq!(
+            notification.get_location(),
+            Some("/issue/abc#comment-2".to_string())
+        );
+        // Old notifications have no kind
+        let old: Notification = serde_yaml::from_str(
+            "id: 1\ndate_created: 2020-03-01T06:00:00Z\nis_new: true\nsubject: Hello\nlocation: ~\n",
+        )
+        .unwrap();
+        assert_eq!(old.get_kind(), &NotificationKind::Text);
+    }
+
+    #[test]
+    fn test_location() {
+        let customer = Location::Customer { id: "c1".into() };
+        assert_eq!(customer.get_location_url(), "/customer/c1");
+        assert_eq!(
+            Location::User { id: "peter".into() }.get_location_url(),
+            "/user/peter"
+        );
+        assert_eq!(
+            Location::Product { id: "SKU-1".into() }.get_location_url(),
+            "/product/SKU-1"
+        );
+        assert_eq!(
+            Location::Order { id: "o1".into() }.get_location_url(),
+            "/order/o1"
+        );
+        assert_eq!(Location::None.get_location_url(), "");
+        assert_eq!(
+            customer.is_same_target(&Location::Customer { id: "c1".into() }),
+            true
+        );
+        assert_eq!(
+            customer.is_same_target(&Location::Customer { id: "c2".into() }),
+            false
+        );
+        assert_eq!(
+            customer.is_same_target(&Location::User { id: "c1".into() }),
+            false
+        );
+        let yaml = serde_yaml::to_string(&Location::Order { id: "o1".into() }).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<Location>(&yaml).unwrap(),
+            Location::Order { id: "o1".into() }
+        );
+        assert_eq!(customer.validate().is_ok(), true);
+        assert_eq!(Location::Raw("/news".into()).validate().is_ok(), true);
+        assert_eq!(
+            Location::Raw("javascript:alert(1)".into())
+                .validate()
+                .is_ok(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_grouping() {
+        let comment = |actor: Actor, section: &str| {
+            Notification::from_kind(
+                NotificationKind::IssueCommented {
+                    actor,
+                    title: "Broken pump".into(),
+                },
+                Location::Issue {
+                    id: "abc".into(),
+                    section: Some(section.into()),
+                },
+            )
+        };
+        let peter = Actor::new("peter", "Peter");
+        let anna = Actor::new("anna", "Anna");
+        let mut container = NotificationContainer::new("demo".into());
+        container.add(comment(peter.clone(), "comment-1"));
+        container.add(Notification::new("Other".into()));
+        container.add(comment(anna.clone(), "comment-2"));
+        let id = container.add(comment(peter.clone(), "comment-3"));
+        assert_eq!(id, 4);
+        assert_eq!(container.get_notifications().len(), 2);
+        let group = container.find_by_id(4).unwrap();
+        assert_eq!(group.get_count(), 3);
+        assert_eq!(group.get_actors(), &vec![peter.clone(), anna.clone()]);
+        assert_eq!(
+            group.get_subject(),
+            "3 új hozzászólás a következő issue-hoz: Broken pump (Peter, Anna)"
+        );
+        assert_eq!(
+            group.get_location(),
+            Some("/issue/abc#comment-3".to_string())
+        );
+        assert_eq!(container.get_unread_count(), 2);
+        // Seen notifications are not collapsed
+        container.get_by_id(4).unwrap().set_seen();
+        container.add(comment(anna.clone(), "comment-4"));
+        assert_eq!(container.get_notifications().len(), 3);
+        assert_eq!(container.find_by_id(5).unwrap().get_count(), 1);
+        // Other targets are not collapsed
+        let mut other = comment(anna, "comment-1");
+        other.set_location(Location::Issue {
+            id: "xyz".into(),
+            section: None,
+        });
+        assert!(container.find_group(&other).is_none());
+        // Plain texts are not collapsed
+        container.add(Notification::new("Other".into()));
+        assert_eq!(container.get_notifications().len(), 4);
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email::{MailTransport, Message};
+use crate::error::Error::*;
+use crate::login::generate_token_id;
+pub use crate::model::version::outbox::v1::{OutboxEmail, OutboxStatus};
+use crate::prelude::*;
+use chrono::prelude::*;
+use chrono::Duration;
+
+// After this many failed attempts the email becomes a dead letter
+pub const MAX_ATTEMPTS: u32 = 8;
+// Delay after the first failed attempt, doubled by each failure
+const BASE_BACKOFF_SECONDS: i64 = 60;
+// Max delay between two attempts
+const MAX_BACKOFF_SECONDS: i64 = 6 * 60 * 60;
+// Dead letters keep their content for manual retry this long
+pub const DEAD_LETTER_RETENTION_DAYS: i64 = 30;
+
+impl OutboxEmail {
+    pub fn new(message: Message) -> Self {
+        let now = Utc::now();
+        OutboxEmail {
+            id: generate_token_id(),
+            to: message.get_to().into(),
+            subject: message.get_subject().into(),
+            body: message.get_body().into(),
+            html: message.get_html().map(|h| h.to_string()),
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            date_created: now,
+            date_sent: None,
+            is_redacted: false,
+        }
+    }
+    pub fn get_to(&self) -> &str {
+        &self.to
+    }
+    pub fn get_subject(&self) -> &str {
+        &self.subject
+    }
+    pub fn get_body(&self) -> &str {
+        &self.body
+    }
+    pub fn get_status(&self) -> OutboxStatus {
+        self.status
+    }
+    pub fn get_attempts(&self) -> u32 {
+        self.attempts
+    }
+    pub fn get_last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+    pub fn get_date_created(&self) -> DateTime<Utc> {
+        self.date_created
+    }
+    pub fn is_redacted(&self) -> bool {
+        self.is_redacted
+    }
+    pub fn get_message(&self) -> AppResult<Message> {
+        let message = Message::new(&self.to, &self.subject, &self.body)?;
+        Ok(match &self.html {
+            Some(html) => message.with_html(html.clone()),
+            None => message,
+        })
+    }
+    /**
+     * Delivery attempt through the transport
+     * The result should be registered with `register_delivery`.
+     */
+    pub fn send(&self, transport: &dyn MailTransport) -> AppResult<()> {
+        transport.send(&self.get_message()?)
+    }
+    /**
+     * Pending and its next attempt time has come
+     */
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.status == OutboxStatus::Pending && self.next_attempt_at <= now
+    }
+    /**
+     * Register a successful delivery
+     * The content is not needed anymore, so it is cleared.
+     */
+    pub fn mark_sent(&mut self, now: DateTime<Utc>) {
+        self.status = OutboxStatus::Sent;
+        self.date_sent = Some(now);
+        self.last_error = None;
+        self.redact();
+    }
+    /**
+     * Clear body and HTML
+     * Recipient and subject are kept for the admin listing.
+     */
+    pub fn redact(&mut self) {
+        self.body = String::new();
+        self.html = None;
+        self.is_redacted = true;
+    }
+    /**
+     * Content should be cleared by the retention sweep
+     * Sent emails (stored before redaction on send), and
+     * dead letters older than DEAD_LETTER_RETENTION_DAYS.
+     */
+    pub fn needs_redact(&self, now: DateTime<Utc>) -> bool {
+        if self.is_redacted {
+            return false;
+        }
+        match self.status {
+            OutboxStatus::Pending => false,
+            OutboxStatus::Sent => true,
+            OutboxStatus::Failed => {
+                self.date_created + Duration::days(DEAD_LETTER_RETENTION_DAYS) <= now
+            }
+        }
+    }
+    /**
+     * Register a failed delivery attempt
+     * Schedules the next attempt with exponential backoff,
+     * or moves the email to dead letter after MAX_ATTEMPTS.
+     */
+    pub fn mark_failed(&mut self, error: String, now: DateTime<Utc>) {
+        self.attempts += 1;
+        self.last_error = Some(error);
+        if self.attempts >= MAX_ATTEMPTS {
+            self.status = OutboxStatus::Failed;
+        } else {
+            self.next_attempt_at = now + backoff_for(self.attempts);
+        }
+    }
+    /**
+     * Register the result of a delivery attempt
+     */
+    pub fn register_delivery(&mut self, result: &AppResult<()>, now: DateTime<Utc>) {
+        match result {
+            Ok(_) => self.mark_sent(now),
+            Err(err) => self.mark_failed(format!("{}", err), now),
+        }
+    }
+    /**
+     * Manual retry of a dead letter
+     * Starts again with a fresh attempt counter.
+     */
+    pub fn retry(&mut self, now: DateTime<Utc>) -> AppResult<()> {
+        if self.status != OutboxStatus::Failed {
+            return Err(BadRequest("Csak sikertelen email küldhető újra".to_owned()));
+        }
+        if self.is_redacted {
+            return Err(BadRequest(
+                "Az email tartalma már törölve lett, nem küldhető újra".to_owned(),
+            ));
+        }
+        self.status = OutboxStatus::Pending;
+        self.attempts = 0;
+        self.next_attempt_at = now;
+        Ok(())
+    }
+}
+
+/**
+ * Delay before the next attempt after n failed attempts
+ */
+pub fn backoff_for(attempts: u32) -> Duration {
+    let exponent = attempts.saturating_sub(1).min(16);
+    Duration::seconds(std::cmp::min(
+        BASE_BACKOFF_SECONDS * 2i64.pow(exponent),
+        MAX_BACKOFF_SECONDS,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::MemoryTransport;
+
+    fn demo_email() -> OutboxEmail {
+        OutboxEmail::new(
+            Message::new("demo@user.com", "Subject", "Body")
+                .unwrap()
+                .with_html("<p>Body</p>".into()),
+        )
+    }
+
+    #[test]
+    fn test_new_email() {
+        let email = demo_email();
+        assert_eq!(email.get_status(), OutboxStatus::Pending);
+        assert_eq!(email.is_due(Utc::now()), true);
+        assert_eq!(email.get_message().unwrap().get_html(), Some("<p>Body</p>"));
+    }
+
+    #[test]
+    fn test_backoff_for() {
+        assert_eq!(backoff_for(1), Duration::seconds(60));
+        assert_eq!(backoff_for(2), Duration::seconds(120));
+        assert_eq!(backoff_for(4), Duration::seconds(480));
+        assert_eq!(backoff_for(30), Duration::hours(6));
+    }
+
+    #[test]
+    fn test_retry_schedule() {
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let mut email = demo_email();
+        email.mark_failed("Connection refused".into(), now);
+        assert_eq!(email.get_status(), OutboxStatus::Pending);
+        assert_eq!(email.is_due(now), false);
+        assert_eq!(email.is_due(now + Duration::seconds(60)), true);
+        assert_eq!(email.get_last_error(), Some("Connection refused"));
+        // Retry is only for dead letters
+        assert_eq!(email.retry(now).is_ok(), false);
+        for _ in 1..MAX_ATTEMPTS {
+            email.mark_failed("Connection refused".into(), now);
+        }
+        assert_eq!(email.get_status(), OutboxStatus::Failed);
+        assert_eq!(email.is_due(now + Duration::days(1)), false);
+        email.retry(now).unwrap();
+        assert_eq!(email.get_attempts(), 0);
+        assert_eq!(email.is_due(now), true);
+        email.mark_sent(now);
+        assert_eq!(email.get_status(), OutboxStatus::Sent);
+        assert_eq!(email.get_last_error(), None);
+    }
+
+    #[test]
+    fn test_redact() {
+        let now = Utc::now();
+        let mut email = demo_email();
+        assert_eq!(email.needs_redact(now), false);
+        email.mark_sent(now);
+        assert_eq!(email.is_redacted(), true);
+        assert_eq!(email.get_body(), "");
+        assert_eq!(email.html, None);
+        assert_eq!(email.get_to(), "demo@user.com");
+        assert_eq!(email.needs_redact(now), false);
+        // Sent before redaction existed
+        let mut email = demo_email();
+        email.status = OutboxStatus::Sent;
+        assert_eq!(email.needs_redact(now), true);
+        // Dead letter is kept for manual retry for a while
+        let mut email = demo_email();
+        for _ in 0..MAX_ATTEMPTS {
+            email.mark_failed("Connection refused".into(), now);
+        }
+        let created = email.get_date_created();
+        assert_eq!(email.needs_redact(created + Duration::days(29)), false);
+        assert_eq!(
+            email.needs_redact(created + Duration::days(DEAD_LETTER_RETENTION_DAYS)),
+            true
+        );
+        email.redact();
+        assert_eq!(email.get_body(), "");
+        assert_eq!(email.retry(now).is_ok(), false);
+    }
+
+    #[test]
+    fn test_deliver() {
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let transport = MemoryTransport::new();
+        let mut email = demo_email();
+        let result = email.send(&transport);
+        email.register_delivery(&result, now);
+        assert_eq!(email.get_status(), OutboxStatus::Sent);
+        assert_eq!(transport.sent().len(), 1);
+        assert_eq!(transport.sent()[0].get_to(), "demo@user.com");
+        let mut email = demo_email();
+        email.register_delivery(&Err(InternalError("Timeout".into())), now);
+        assert_eq!(email.get_attempts(), 1);
+        assert_eq!(email.get_last_error(), Some("Internal error: Timeout"));
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::error::Error::*;
+use crate::login::generate_token_id;
+pub use crate::model::version::session::v1::Session;
+use crate::password::*;
+use crate::prelude::*;
+use chrono::prelude::*;
+
+impl Session {
+    pub fn new(userid: String, expires_at: DateTime<Utc>) -> Self {
+        Session {
+            id: generate_token_id(),
+            userid,
+            date_created: Utc::now(),
+            expires_at,
+            date_revoked: None,
+            refresh_generation: 0,
+            refresh_hash: "".into(),
+        }
+    }
+    pub fn get_userid(&self) -> &str {
+        &self.userid
+    }
+    pub fn get_date_created(&self) -> DateTime<Utc> {
+        self.date_created
+    }
+    pub fn get_expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+    /**
+     * Session is revoked, when its user logged out
+     */
+    pub fn is_revoked(&self) -> bool {
+        self.date_revoked.is_some()
+    }
+    /**
+     * Revoke session
+     * Returns false if it was already revoked
+     */
+    pub fn revoke(&mut self) -> bool {
+        if self.is_revoked() {
+            return false;
+        }
+        self.date_revoked = Some(Utc::now());
+        true
+    }
+    /**
+     * Session is active at a given time,
+     * if its not revoked and not expired yet
+     */
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        !self.is_revoked() && now < self.expires_at
+    }
+    /**
+     * Issue a new refresh token
+     * Format: {session_id}.{generation}.{secret}
+     * Only the hash of the secret is stored, and
+     * every former refresh token becomes outdated.
+     */
+    pub fn issue_refresh_token(&mut self) -> AppResult<String> {
+        let secret = generate_random_password(Some(32))?;
+        self.refresh_hash = hash_secret(&secret)?;
+        self.refresh_generation += 1;
+        Ok(format!(
+            "{}.{}.{}",
+            self.id, self.refresh_generation, secret
+        ))
+    }
+    /**
+     * Rotate refresh token
+     * Returns a new refresh token if the given one is the
+     * current one. If an outdated refresh token is used,
+     * then someone reuses a stolen token, so we revoke
+     * the whole token family.
+     */
+    pub fn rotate_refresh_token(&mut self, token: &str, now: DateTime<Utc>) -> AppResult<String> {
+        let (session_id, generation, secret) = match parse_refresh_token(token) {
+            Some(parts) => parts,
+            None => return Err(BadRequest("Hibás refresh token".to_owned())),
+        };
+        if session_id != self.id {
+            return Err(BadRequest("Hibás refresh token".to_owned()));
+        }
+        if !self.is_active_at(now) {
+            return Err(BadRequest(
+                "Lejárt vagy kijelentkeztetett munkamenet".to_owned(),
+            ));
+        }
+        if generation < self.refresh_generation {
+            self.revoke();
+            return Err(BadRequest(
+                "Már felhasznált refresh token, a munkamenet kijelentkeztetve".to_owned(),
+            ));
+        }
+        if generation != self.refresh_generation
+            || !verify_password_from_hash(secret, &self.refresh_hash)?
+        {
+            return Err(BadRequest("Hibás refresh token".to_owned()));
+        }
+        self.issue_refresh_token()
+    }
+}
+
+/**
+ * Split refresh token into parts
+ * (session_id, generation, secret)
+ */
+pub fn parse_refresh_token(token: &str) -> Option<(&str, usize, &str)> {
+    let mut parts = token.splitn(3, '.');
+    let session_id = parts.next()?;
+    let generation = parts.next()?.parse::<usize>().ok()?;
+    let secret = parts.next()?;
+    if session_id.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some((session_id, generation, secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn demo_session() -> Session {
+        Session::new("demo".into(), Utc::now() + Duration::days(1))
+    }
+
+    #[test]
+    fn test_parse_refresh_token() {
+        assert_eq!(
+            parse_refresh_token("abc.2.secret"),
+            Some(("abc", 2, "secret"))
+        );
+        assert_eq!(parse_refresh_token("abc.x.secret"), None);
+        assert_eq!(parse_refresh_token("abc.2"), None);
+        assert_eq!(parse_refresh_token(".2.secret"), None);
+    }
+
+    #[test]
+    fn test_rotate_refresh_token() {
+        let mut session = demo_session();
+        let first = session.issue_refresh_token().unwrap();
+        let second = session.rotate_refresh_token(&first, Utc::now()).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(session.is_revoked(), false);
+        let third = session.rotate_refresh_token(&second, Utc::now()).unwrap();
+        assert_eq!(parse_refresh_token(&third).unwrap().1, 3);
+    }
+
+    #[test]
+    fn test_refresh_token_reuse_revokes_session() {
+        let mut session = demo_session();
+        let first = session.issue_refresh_token().unwrap();
+        let second = session.rotate_refresh_token(&first, Utc::now()).unwrap();
+        // Reuse the old one
+        assert_eq!(
+            session.rotate_refresh_token(&first, Utc::now()).is_ok(),
+            false
+        );
+        assert_eq!(session.is_revoked(), true);
+        // Now the whole family is revoked
+        assert_eq!(
+            session.rotate_refresh_token(&second, Utc::now()).is_ok(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_refresh_token_wrong_secret() {
+        let mut session = demo_session();
+        let token = session.issue_refresh_token().unwrap();
+        let (id, generation, _) = parse_refresh_token(&token).unwrap();
+        let forged = format!("{}.{}.{}", id, generation, "wrongsecret");
+        assert_eq!(
+            session.rotate_refresh_token(&forged, Utc::now()).is_ok(),
+            false
+        );
+        // Wrong secret does not revoke the session
+        assert_eq!(session.is_revoked(), false);
+        assert_eq!(
+            session.rotate_refresh_token(&token, Utc::now()).is_ok(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_refresh_token_expired() {
+        let mut session = demo_session();
+        let token = session.issue_refresh_token().unwrap();
+        let later = Utc::now() + Duration::days(2);
+        assert_eq!(session.rotate_refresh_token(&token, later).is_ok(), false);
+    }
+}
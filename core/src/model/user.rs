@@ -15,13 +15,20 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::email;
-use crate::email::*;
+use crate::error::Error;
 use crate::error::Error::*;
-pub use crate::model::version::user::v1::User;
+use crate::model::notification::NotificationPreferences;
+pub use crate::model::version::user::v2::{PasswordReset, Role, TwoFactor, User};
 use crate::password::*;
 use crate::prelude::*;
+use crate::throttle::*;
+use crate::totp;
 use chrono::prelude::*;
+use chrono::Duration;
+use std::str::FromStr;
+
+// Number of recovery codes created at 2FA enrollment
+const RECOVERY_CODE_COUNT: usize = 8;
 
 impl User {
     pub fn new(
@@ -105,10 +112,89 @@ impl User {
             created_by,
             // TODO: Attach default customer at initialisation process
             customers: Vec::new(),
+            roles: vec![Role::Staff],
+            is_disabled: false,
+            password_reset: None,
+            login_attempts: LoginAttempts::new(),
+            two_factor: None,
+            notification_preferences: NotificationPreferences::default(),
         })
     }
 }
 
+impl FromStr for Role {
+    type Err = Error;
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role.to_lowercase().as_str() {
+            "admin" => Ok(Role::Admin),
+            "staff" => Ok(Role::Staff),
+            "cashier" => Ok(Role::Cashier),
+            "customer" => Ok(Role::Customer),
+            _ => Err(BadRequest(format!("Ismeretlen szerepkör: {}", role))),
+        }
+    }
+}
+
+/// Permissions checked by the API per route
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    UserRead,
+    UserWrite,
+    UserRoleWrite,
+    CustomerRead,
+    CustomerWrite,
+    IssueRead,
+    IssueWrite,
+    IssueAssign,
+    EmailManage,
+    NotificationBroadcast,
+    LabelManage,
+}
+
+impl Role {
+    /**
+     * Permissions granted by the role
+     */
+    pub fn permissions(&self) -> Vec<Permission> {
+        use Permission::*;
+        match self {
+            Role::Admin => vec![
+                UserRead,
+                UserWrite,
+                UserRoleWrite,
+                CustomerRead,
+                CustomerWrite,
+                IssueRead,
+                IssueWrite,
+                IssueAssign,
+                EmailManage,
+                NotificationBroadcast,
+                LabelManage,
+            ],
+            Role::Staff => vec![
+                UserRead,
+                CustomerRead,
+                CustomerWrite,
+                IssueRead,
+                IssueWrite,
+                IssueAssign,
+                LabelManage,
+            ],
+            Role::Cashier => vec![CustomerRead, IssueRead, IssueWrite],
+            Role::Customer => Vec::new(),
+        }
+    }
+}
+
+impl Permission {
+    /**
+     * Is the permission granted by any of the roles
+     */
+    pub fn is_granted_by(&self, roles: &[Role]) -> bool {
+        roles.iter().any(|r| r.permissions().contains(self))
+    }
+}
+
 impl User {
     pub fn get_user_id(&self) -> &str {
         &self.id
@@ -174,47 +260,278 @@ impl User {
     pub fn get_customers(&self) -> Vec<String> {
         self.customers.clone()
     }
+    pub fn get_roles(&self) -> Vec<Role> {
+        self.roles.clone()
+    }
+    /**
+     * Set roles
+     * Duplicates are removed, order is kept
+     */
+    pub fn set_roles(&mut self, roles: Vec<Role>) {
+        let mut result: Vec<Role> = Vec::new();
+        for role in roles {
+            if !result.contains(&role) {
+                result.push(role);
+            }
+        }
+        self.roles = result;
+    }
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+    /**
+     * Check if any of the user roles grants
+     * the given permission
+     */
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        permission.is_granted_by(&self.roles)
+    }
+    pub fn is_disabled(&self) -> bool {
+        self.is_disabled
+    }
+    /**
+     * Disable user
+     * Disabled user cannot log in
+     */
+    pub fn disable(&mut self) {
+        self.is_disabled = true;
+    }
+    pub fn enable(&mut self) {
+        self.is_disabled = false;
+    }
+    pub fn get_login_attempts(&self) -> &LoginAttempts {
+        &self.login_attempts
+    }
+    /**
+     * Login is locked after too many failed attempts
+     */
+    pub fn is_login_locked(&self, now: DateTime<Utc>) -> bool {
+        self.login_attempts.is_locked(now)
+    }
+    pub fn register_failed_login(&mut self, now: DateTime<Utc>) {
+        self.login_attempts
+            .register_failure(&ThrottlePolicy::user(), now);
+    }
+    /**
+     * Unlock login and reset failed attempt counter
+     */
+    pub fn reset_failed_logins(&mut self) {
+        self.login_attempts.reset();
+    }
+    pub fn get_notification_preferences(&self) -> &NotificationPreferences {
+        &self.notification_preferences
+    }
+    pub fn set_notification_preferences(&mut self, preferences: NotificationPreferences) {
+        self.notification_preferences = preferences;
+    }
     pub fn get_password_hash(&self) -> &str {
         &self.password_hash
     }
+    /**
+     * Check the password against the policy and
+     * this user's ID and email, without setting it
+     */
+    pub fn validate_password(&self, password: &str) -> AppResult<()> {
+        PasswordPolicy::from_env()?.validate_for_user(password, &self.id, &self.email)
+    }
     pub fn set_password(&mut self, password: String) -> AppResult<()> {
-        validate_password(&password)?;
+        self.validate_password(&password)?;
         self.password_hash = hash_password(&password)?;
         Ok(())
     }
+    /**
+     * Hash the current password again
+     * without the policy check, to upgrade an old hash
+     * at login. Use `set_password` to change the password.
+     */
+    pub fn rehash_password(&mut self, password: &str) -> AppResult<()> {
+        self.password_hash = hash_password(password)?;
+        Ok(())
+    }
 
-    // TODO: Maybe should be at a higher level using User trait reference as input?
-    // Maybe this?
-    // => fn reset_password<T: User>(user: &T) -> Result<(), String> {...}
-    pub fn reset_password(&mut self) -> AppResult<()> {
-        let new_password = generate_random_password(None)?;
-        self.password_hash = hash_password(&new_password)?;
-        match email::new(
-            &self.get_user_email(),
-            "Gardenova ÚJ JELSZÓ",
-            &format!(
-                "A felhasználói neved: {}\nAz új jelszavad: {}",
-                self.get_user_id(),
-                &new_password
-            ),
-        )
-        .send()
-        {
-            Ok(_) => (),
-            // TODO:
-            // Use email pool, in case of email service failure.
-            // Instead of using error in case of error - directly here -,
-            // We should say its Ok(()) now, and in case of error, the email pool,
-            // should manage the trials.
-            Err(msg) => {
-                return Err(InternalError(format!(
-                    "Az új jelszó elkészült, de hiba az email elküldése során. A hibaüzenet: {}",
-                    msg
-                )))
+    /**
+     * Request password reset
+     * Creates a single-use reset token valid for `valid_for`,
+     * and returns it to send it to the user. The current
+     * password stays valid until the reset is confirmed.
+     * Token format: {userid}.{secret}
+     */
+    pub fn request_password_reset(&mut self, valid_for: Duration) -> AppResult<String> {
+        let secret = generate_random_password(Some(32))?;
+        self.password_reset = Some(PasswordReset {
+            token_hash: hash_secret(&secret)?,
+            expires_at: Utc::now() + valid_for,
+        });
+        Ok(format!("{}.{}", self.id, secret))
+    }
+    /**
+     * Confirm password reset
+     * Validates the reset token, then sets the new password
+     * and removes the reset request, so the token cannot be
+     * used again.
+     */
+    pub fn confirm_password_reset(
+        &mut self,
+        token: &str,
+        new_password: String,
+        now: DateTime<Utc>,
+    ) -> AppResult<()> {
+        let invalid = || BadRequest("Hibás vagy lejárt jelszó visszaállító token".to_owned());
+        let secret = match parse_password_reset_token(token) {
+            Some((userid, secret)) if userid == self.id => secret,
+            _ => return Err(invalid()),
+        };
+        let token_hash = match &self.password_reset {
+            Some(reset) if now < reset.expires_at => reset.token_hash.clone(),
+            _ => return Err(invalid()),
+        };
+        if !verify_password_from_hash(secret, &token_hash)? {
+            return Err(invalid());
+        }
+        self.set_password(new_password)?;
+        self.password_reset = None;
+        Ok(())
+    }
+    pub fn has_two_factor(&self) -> bool {
+        match &self.two_factor {
+            Some(two_factor) => two_factor.is_enabled,
+            None => false,
+        }
+    }
+    /**
+     * Start TOTP enrollment
+     * Creates a new secret and returns it in base32.
+     * 2FA is not enabled until the first code is confirmed.
+     */
+    pub fn enroll_two_factor(&mut self) -> AppResult<String> {
+        if self.has_two_factor() {
+            return Err(BadRequest(
+                "A kétlépcsős azonosítás már be van kapcsolva".to_owned(),
+            ));
+        }
+        let secret = totp::generate_secret();
+        self.two_factor = Some(TwoFactor {
+            secret: secret.clone(),
+            is_enabled: false,
+            recovery_codes: Vec::new(),
+            last_used_step: 0,
+        });
+        Ok(secret)
+    }
+    /**
+     * Confirm TOTP enrollment with the first valid code
+     * Enables 2FA and returns the recovery codes in plain text.
+     * Only their hashes are stored, so this is the only time
+     * the user can see them.
+     */
+    pub fn confirm_two_factor(&mut self, code: &str, now: DateTime<Utc>) -> AppResult<Vec<String>> {
+        let two_factor = match &mut self.two_factor {
+            Some(two_factor) if !two_factor.is_enabled => two_factor,
+            _ => {
+                return Err(BadRequest(
+                    "Nincs folyamatban kétlépcsős azonosítás beállítás".to_owned(),
+                ))
             }
+        };
+        let step = match totp::verify(&two_factor.secret, code, now.timestamp()) {
+            Some(step) => step,
+            None => return Err(BadRequest("Hibás ellenőrző kód".to_owned())),
+        };
+        let mut codes = Vec::new();
+        let mut hashes = Vec::new();
+        for _ in 0..RECOVERY_CODE_COUNT {
+            let code = generate_random_password(Some(10))?;
+            hashes.push(hash_secret(&code)?);
+            codes.push(code);
+        }
+        two_factor.is_enabled = true;
+        two_factor.recovery_codes = hashes;
+        two_factor.last_used_step = step;
+        Ok(codes)
+    }
+    /**
+     * Disable 2FA
+     * Requires a valid TOTP or recovery code
+     */
+    pub fn disable_two_factor(&mut self, code: &str, now: DateTime<Utc>) -> AppResult<()> {
+        if !self.has_two_factor() {
+            return Err(BadRequest(
+                "A kétlépcsős azonosítás nincs bekapcsolva".to_owned(),
+            ));
         }
+        self.verify_two_factor(code, now)?;
+        self.two_factor = None;
         Ok(())
     }
+    /**
+     * Verify second login step
+     * Accepts a TOTP code, or an unused recovery code.
+     * A TOTP code is accepted only once, and a used
+     * recovery code is removed. Failed attempts count
+     * towards the login lockout.
+     */
+    pub fn verify_two_factor(&mut self, code: &str, now: DateTime<Utc>) -> AppResult<()> {
+        let invalid = || BadRequest("Hibás ellenőrző kód".to_owned());
+        if self.is_login_locked(now) {
+            return Err(BadRequest(
+                "Túl sok sikertelen próbálkozás, próbálja később".to_owned(),
+            ));
+        }
+        let two_factor = match &mut self.two_factor {
+            Some(two_factor) if two_factor.is_enabled => two_factor,
+            _ => return Err(invalid()),
+        };
+        let mut is_valid = false;
+        match totp::verify(&two_factor.secret, code, now.timestamp()) {
+            Some(step) if step > two_factor.last_used_step => {
+                two_factor.last_used_step = step;
+                is_valid = true;
+            }
+            Some(_) => (),
+            None => {
+                let code = code.trim().to_lowercase();
+                let mut used: Option<usize> = None;
+                for (index, hash) in two_factor.recovery_codes.iter().enumerate() {
+                    if verify_password_from_hash(&code, hash)? {
+                        used = Some(index);
+                        break;
+                    }
+                }
+                if let Some(index) = used {
+                    two_factor.recovery_codes.remove(index);
+                    is_valid = true;
+                }
+            }
+        }
+        if is_valid {
+            self.reset_failed_logins();
+            Ok(())
+        } else {
+            self.register_failed_login(now);
+            Err(invalid())
+        }
+    }
+    /**
+     * Number of unused recovery codes
+     */
+    pub fn get_recovery_code_count(&self) -> usize {
+        match &self.two_factor {
+            Some(two_factor) => two_factor.recovery_codes.len(),
+            None => 0,
+        }
+    }
+}
+
+/**
+ * Split password reset token into parts
+ * (userid, secret)
+ */
+pub fn parse_password_reset_token(token: &str) -> Option<(&str, &str)> {
+    let (userid, secret) = token.split_once('.')?;
+    if userid.is_empty() || secret.is_empty() {
+        return None;
+    }
+    Some((userid, secret))
 }
 
 #[cfg(test)]
@@ -307,25 +624,217 @@ mod tests {
         let password: &str = "HelloWorld749";
         assert_eq!(user.get_password_hash(), ""); // should be None
         assert_eq!(user.set_password("pass".into()).is_ok(), false); // should be err
-        assert_eq!(user.set_password("PAss7".into()).is_ok(), true); // should be err
+        assert_eq!(user.set_password("PAss7".into()).is_ok(), false); // too short
         assert_eq!(user.set_password("password".into()).is_ok(), false); // should be err
         assert_eq!(user.set_password("Password".into()).is_ok(), false); // should be err
         assert_eq!(user.set_password("PAssword".into()).is_ok(), false); // should be err
-        assert_eq!(user.set_password("PAssword7".into()).is_ok(), true); // should be ok
+        assert_eq!(user.set_password("Password123".into()).is_ok(), false); // common password
+        assert_eq!(user.set_password("MyDemoHome42".into()).is_ok(), false); // contains user ID
+        assert_eq!(user.validate_password("MyDemoHome42").is_ok(), false); // contains user ID
+        assert_eq!(user.get_password_hash(), ""); // still not set
+        assert_eq!(user.validate_password("GreenHouse42").is_ok(), true);
+        assert_eq!(user.set_password("GreenHouse42".into()).is_ok(), true); // should be ok
         assert_eq!(user.set_password(password.into()).is_ok(), true); // should be ok
         assert_eq!(
             verify_password_from_hash(password, user.get_password_hash()).unwrap(),
             true
         );
     }
-    // #[test]
-    // #[ignore]
-    // fn test_reset_password() {
-    //     let mut user: UserV1 = UserV1::new("demo".into(), "user".into(), "demo@user.com".into());
-    //     user.set_user_email(&env::var("E_TO_TEST_EMAIL").unwrap())
-    //         .unwrap();
-    //     user.set_user_name(&env::var("E_TO_TEST_NAME").unwrap())
-    //         .unwrap();
-    //     assert_eq!(user.reset_password().is_ok(), true);
-    // }
+    #[test]
+    fn test_user_roles() {
+        let mut user: User = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        // New users are staff members by default
+        assert_eq!(user.get_roles(), vec![Role::Staff]);
+        assert_eq!(user.has_permission(Permission::CustomerWrite), true);
+        assert_eq!(user.has_permission(Permission::UserWrite), false);
+        user.set_roles(vec![Role::Cashier, Role::Cashier]);
+        assert_eq!(user.get_roles(), vec![Role::Cashier]);
+        assert_eq!(user.has_permission(Permission::CustomerWrite), false);
+        user.set_roles(vec![Role::Cashier, Role::Admin]);
+        assert_eq!(user.has_role(Role::Admin), true);
+        assert_eq!(user.has_permission(Permission::UserRoleWrite), true);
+        user.set_roles(Vec::new());
+        assert_eq!(user.has_permission(Permission::IssueRead), false);
+        assert_eq!(
+            Permission::IssueAssign.is_granted_by(&[Role::Cashier]),
+            false
+        );
+        assert_eq!(
+            Permission::IssueAssign.is_granted_by(&[Role::Cashier, Role::Staff]),
+            true
+        );
+        assert_eq!("Admin".parse::<Role>().unwrap(), Role::Admin);
+        assert_eq!("boss".parse::<Role>().is_err(), true);
+    }
+
+    #[test]
+    fn test_user_disable() {
+        let mut user: User = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        assert_eq!(user.is_disabled(), false);
+        user.disable();
+        assert_eq!(user.is_disabled(), true);
+        user.enable();
+        assert_eq!(user.is_disabled(), false);
+    }
+
+    #[test]
+    fn test_reset_password() {
+        let mut user: User = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        user.set_password("HelloWorld749".into()).unwrap();
+        let token = user.request_password_reset(Duration::hours(1)).unwrap();
+        assert_eq!(parse_password_reset_token(&token).unwrap().0, "demo");
+        // Old password is still valid
+        assert_eq!(
+            verify_password_from_hash("HelloWorld749", user.get_password_hash()).unwrap(),
+            true
+        );
+        // Wrong token
+        assert_eq!(
+            user.confirm_password_reset("demo.wrong", "NewPassword12".into(), Utc::now())
+                .is_ok(),
+            false
+        );
+        // Weak password keeps the reset request
+        assert_eq!(
+            user.confirm_password_reset(&token, "weak".into(), Utc::now())
+                .is_ok(),
+            false
+        );
+        assert_eq!(
+            user.confirm_password_reset(&token, "NewPassword12".into(), Utc::now())
+                .is_ok(),
+            true
+        );
+        assert_eq!(
+            verify_password_from_hash("NewPassword12", user.get_password_hash()).unwrap(),
+            true
+        );
+        // Token is single-use
+        assert_eq!(
+            user.confirm_password_reset(&token, "OtherPassword12".into(), Utc::now())
+                .is_ok(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_reset_password_expired() {
+        let mut user: User = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        let token = user.request_password_reset(Duration::hours(1)).unwrap();
+        let later = Utc::now() + Duration::hours(2);
+        assert_eq!(
+            user.confirm_password_reset(&token, "NewPassword12".into(), later)
+                .is_ok(),
+            false
+        );
+    }
+
+    #[test]
+    fn test_two_factor() {
+        let mut user: User = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let code_at = |secret: &str, time: DateTime<Utc>| {
+            let secret = totp::base32_decode(secret).unwrap();
+            totp::totp(&secret, time.timestamp(), totp::DIGITS)
+        };
+        let secret = user.enroll_two_factor().unwrap();
+        // Not enabled until confirmed
+        assert_eq!(user.has_two_factor(), false);
+        assert_eq!(user.confirm_two_factor("000000", now).is_ok(), false);
+        let recovery_codes = user
+            .confirm_two_factor(&code_at(&secret, now), now)
+            .unwrap();
+        assert_eq!(user.has_two_factor(), true);
+        assert_eq!(recovery_codes.len(), 8);
+        assert_eq!(user.enroll_two_factor().is_ok(), false);
+        // Same code cannot be used twice
+        assert_eq!(
+            user.verify_two_factor(&code_at(&secret, now), now).is_ok(),
+            false
+        );
+        let later = now + Duration::seconds(60);
+        assert_eq!(
+            user.verify_two_factor(&code_at(&secret, later), later)
+                .is_ok(),
+            true
+        );
+        // Recovery code is single-use
+        assert_eq!(
+            user.verify_two_factor(&recovery_codes[0], later).is_ok(),
+            true
+        );
+        assert_eq!(user.get_recovery_code_count(), 7);
+        assert_eq!(
+            user.verify_two_factor(&recovery_codes[0], later).is_ok(),
+            false
+        );
+        // Disable requires a valid code
+        let latest = later + Duration::seconds(60);
+        assert_eq!(user.disable_two_factor("000000", latest).is_ok(), false);
+        assert_eq!(
+            user.disable_two_factor(&code_at(&secret, latest), latest)
+                .is_ok(),
+            true
+        );
+        assert_eq!(user.has_two_factor(), false);
+    }
+
+    #[test]
+    fn test_two_factor_lockout() {
+        let mut user: User = User::new(
+            "demo".into(),
+            "user".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let secret = user.enroll_two_factor().unwrap();
+        let code = totp::totp(
+            &totp::base32_decode(&secret).unwrap(),
+            now.timestamp(),
+            totp::DIGITS,
+        );
+        user.confirm_two_factor(&code, now).unwrap();
+        for _ in 0..5 {
+            assert_eq!(user.verify_two_factor("000000", now).is_ok(), false);
+        }
+        assert_eq!(user.is_login_locked(now), true);
+    }
 }
@@ -0,0 +1,38 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+use storaget::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Counter {
+    /**
+     * Counter name, e.g. issue
+     */
+    pub id: String,
+    /**
+     * Last value given out
+     */
+    pub value: usize,
+}
+
+// Implement StorageObject for Counter
+impl VecPackMember for Counter {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -30,18 +31,46 @@ pub struct Comment {
     pub liked: Vec<String>,
     /**
      * Comment text
-     * should be markdown ready
+     * Markdown, see crate::markdown
      */
     pub text: String,
+    /**
+     * Mentioned user IDs
+     * Only existing users, validated when the comment is added
+     */
+    #[serde(default)]
+    pub mentions: Vec<String>,
+    /**
+     * Author @userid
+     * Empty for comments created before it was stored,
+     * then the NewComment event created_by is the author.
+     */
+    #[serde(default)]
+    pub created_by: String,
+    /**
+     * Date of the last edit
+     */
+    #[serde(default)]
+    pub date_edited: Option<DateTime<Utc>>,
+    /**
+     * Soft deleted comment
+     * Text is kept, but not shown
+     */
+    #[serde(default)]
+    pub is_deleted: bool,
 }
 
 impl Comment {
-    pub fn new(id: usize, text: String) -> Self {
+    pub fn new(id: usize, text: String, created_by: String) -> Self {
         Comment {
             // TODO: We need to set ID during the add process
             id,
             liked: Vec::new(),
             text,
+            mentions: Vec::new(),
+            created_by,
+            date_edited: None,
+            is_deleted: false,
         }
     }
 }
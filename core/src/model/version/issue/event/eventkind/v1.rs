@@ -28,12 +28,22 @@ pub enum EventKind {
     NewComment(Comment),
     /**
      * New label added
+     * Legacy, before the label registry
      */
     LabelAdded(Label),
     /**
      * Label removed
+     * Legacy, before the label registry
      */
     LabelRemoved(Label),
+    /**
+     * Label attached, by IssueLabel ID
+     */
+    LabelAttached(String),
+    /**
+     * Label detached, by IssueLabel ID
+     */
+    LabelDetached(String),
     /**
      * Issue assigned to another user
      */
@@ -46,4 +56,35 @@ pub enum EventKind {
      * Issue re-opened
      */
     Opened,
+    /**
+     * Issue referenced as #number in a comment
+     * or in the description of another issue
+     * @id: referencing issue ID
+     * @number: referencing issue number
+     * @comment_id: referencing comment ID, 0 for the description
+     */
+    Referenced {
+        id: String,
+        number: usize,
+        comment_id: usize,
+    },
+    /**
+     * Title changed
+     * @previous: title before the change
+     */
+    TitleChanged { previous: String },
+    /**
+     * Description changed
+     * @previous: description before the change
+     */
+    DescriptionChanged { previous: String },
+    /**
+     * Comment text changed
+     * @previous: comment text before the change
+     */
+    CommentEdited { comment_id: usize, previous: String },
+    /**
+     * Comment soft deleted
+     */
+    CommentDeleted { comment_id: usize },
 }
@@ -27,13 +27,19 @@ pub struct Issue {
      * ID
      */
     pub id: String,
+    /**
+     * Sequential issue number, e.g. #142
+     * 0 until backfilled for issues created before numbering
+     */
+    #[serde(default)]
+    pub number: usize,
     /**
      * Issue title
      */
     pub title: String,
     /**
      * Issue description
-     * TODO: should be markdown capable
+     * Markdown, see crate::markdown
      */
     pub description: String,
     /**
@@ -45,9 +51,16 @@ pub struct Issue {
      */
     pub created_by: String,
     /**
-     * Assigned label list
+     * Legacy free-form label list
+     * Moved into the label registry by the label migration,
+     * use label_ids instead.
      */
     pub labels: Vec<Label>,
+    /**
+     * Assigned IssueLabel IDs
+     */
+    #[serde(default)]
+    pub label_ids: Vec<String>,
     /**
      * Assigned to @userid
      */
@@ -0,0 +1,58 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use storaget::*;
+
+/**
+ * Store-wide issue label
+ * Issues refer to it by its ID, so renaming
+ * and recoloring changes it everywhere.
+ */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IssueLabel {
+    pub id: String,
+    /**
+     * e.g.: important
+     * Unique, case insensitive
+     */
+    pub subject: String,
+    /**
+     * Validated CSS color
+     * e.g.: #000000 or white
+     */
+    pub text_color: String,
+    /**
+     * Validated CSS color
+     */
+    pub background_color: String,
+    pub date_created: DateTime<Utc>,
+    pub created_by: String,
+    /**
+     * Deleted labels are kept,
+     * as old issue events refer to them
+     */
+    pub is_deleted: bool,
+}
+
+// Implement StorageObject for IssueLabel
+impl VecPackMember for IssueLabel {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
@@ -15,7 +15,11 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod counter;
 pub mod customer;
 pub mod issue;
+pub mod label;
+pub mod outbox;
 pub mod product;
+pub mod session;
 pub mod user;
@@ -0,0 +1,79 @@
+// Copyright (C) 2020 peter
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use storaget::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /**
+     * Waiting for (next) delivery attempt
+     */
+    Pending,
+    /**
+     * Delivered to the SMTP server
+     */
+    Sent,
+    /**
+     * Dead letter
+     * Max attempts reached, needs manual retry
+     */
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxEmail {
+    pub id: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    /**
+     * Optional HTML alternative of the body
+     */
+    #[serde(default)]
+    pub html: Option<String>,
+    pub status: OutboxStatus,
+    /**
+     * Number of failed delivery attempts
+     */
+    pub attempts: u32,
+    /**
+     * Do not try to send before this date
+     */
+    pub next_attempt_at: DateTime<Utc>,
+    /**
+     * Error text of the last failed attempt
+     */
+    pub last_error: Option<String>,
+    pub date_created: DateTime<Utc>,
+    pub date_sent: Option<DateTime<Utc>>,
+    /**
+     * Body and HTML are cleared
+     * They can contain single-use links, so we
+     * keep them only as long as we need to send them.
+     */
+    #[serde(default)]
+    pub is_redacted: bool,
+}
+
+impl VecPackMember for OutboxEmail {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (C) 2020 peter
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use storaget::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    /**
+     * Token ID
+     * The same as the jti claim of the issued token
+     */
+    pub id: String,
+    /**
+     * Session owner @userid
+     */
+    pub userid: String,
+    /**
+     * Date created, Chrono DateTime<Utc>
+     */
+    pub date_created: DateTime<Utc>,
+    /**
+     * Session is valid until this date
+     * Access tokens are short-lived, this
+     * is the lifetime of the refresh token family.
+     */
+    pub expires_at: DateTime<Utc>,
+    /**
+     * Set when the session is logged out
+     */
+    pub date_revoked: Option<DateTime<Utc>>,
+    /**
+     * Generation of the current refresh token
+     * Increased by each rotation
+     */
+    #[serde(default)]
+    pub refresh_generation: usize,
+    /**
+     * Hash of the current refresh token secret
+     */
+    #[serde(default)]
+    pub refresh_hash: String,
+}
+
+// Implement StorageObject for Session
+impl VecPackMember for Session {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
@@ -16,3 +16,4 @@
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod v1;
+pub mod v2;
@@ -0,0 +1,169 @@
+// Copyright (C) 2020 peter
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::model::notification::NotificationPreferences;
+use crate::model::version::user::v1;
+use crate::throttle::LoginAttempts;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use storaget::*;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /**
+     * Full access, manages users and roles
+     */
+    Admin,
+    /**
+     * Store staff
+     */
+    Staff,
+    /**
+     * Cash register user
+     */
+    Cashier,
+    /**
+     * Customer account
+     */
+    Customer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PasswordReset {
+    /**
+     * Hash of the reset token secret
+     */
+    pub token_hash: String,
+    /**
+     * Reset token is valid until this date
+     */
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TwoFactor {
+    /**
+     * Base32 encoded TOTP secret
+     */
+    pub secret: String,
+    /**
+     * Enabled after the first valid code is confirmed
+     */
+    pub is_enabled: bool,
+    /**
+     * Hashes of the unused recovery codes
+     */
+    pub recovery_codes: Vec<String>,
+    /**
+     * Last accepted time step, codes cannot be reused
+     */
+    pub last_used_step: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub phone: String,
+    pub password_hash: String,
+    pub date_created: DateTime<Utc>,
+    pub created_by: String,
+    pub customers: Vec<String>,
+    pub roles: Vec<Role>,
+    /**
+     * Disabled users cannot log in
+     */
+    #[serde(default)]
+    pub is_disabled: bool,
+    /**
+     * Pending password reset request
+     */
+    #[serde(default)]
+    pub password_reset: Option<PasswordReset>,
+    /**
+     * Failed login attempts and lockout state
+     */
+    #[serde(default)]
+    pub login_attempts: LoginAttempts,
+    /**
+     * TOTP two-factor authentication
+     */
+    #[serde(default)]
+    pub two_factor: Option<TwoFactor>,
+    /**
+     * Notification delivery mode per category
+     */
+    #[serde(default)]
+    pub notification_preferences: NotificationPreferences,
+}
+
+impl Default for User {
+    fn default() -> Self {
+        User {
+            id: String::default(),
+            name: String::default(),
+            email: String::default(),
+            phone: String::default(),
+            password_hash: String::default(),
+            date_created: Utc::now(),
+            created_by: String::default(),
+            customers: Vec::new(),
+            roles: Vec::new(),
+            is_disabled: false,
+            password_reset: None,
+            login_attempts: LoginAttempts::default(),
+            two_factor: None,
+            notification_preferences: NotificationPreferences::default(),
+        }
+    }
+}
+
+impl VecPackMember for User {
+    fn get_id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl TryFrom for User {
+    type TryFrom = v1::User;
+}
+
+// Every user created before roles were introduced
+// was created by the staff, so they become staff members.
+// As none of them is admin, the API promotes or creates one at startup.
+impl From<v1::User> for User {
+    fn from(user: v1::User) -> Self {
+        User {
+            id: user.id,
+            name: user.name,
+            email: user.email,
+            phone: user.phone,
+            password_hash: user.password_hash,
+            date_created: user.date_created,
+            created_by: user.created_by,
+            customers: user.customers,
+            roles: vec![Role::Staff],
+            is_disabled: false,
+            password_reset: None,
+            login_attempts: LoginAttempts::default(),
+            two_factor: None,
+            notification_preferences: NotificationPreferences::default(),
+        }
+    }
+}
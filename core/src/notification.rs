@@ -14,3 +14,124 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email::Message;
+use crate::model::{NotificationContainer, User};
+use crate::prelude::*;
+use crate::template::{escape_html, Template};
+use chrono::prelude::*;
+
+/// # Daily digest email
+/// Renders the digest email of the user, if the digest of the
+/// container is due and there is anything to send. The caller
+/// should mark the digest sent with `set_digest_sent(now)`.
+pub fn digest_email(
+    container: &NotificationContainer,
+    user: &User,
+    link: &str,
+    hour: u32,
+    now: DateTime<Utc>,
+) -> AppResult<Option<Message>> {
+    if !container.is_digest_due(now, hour) {
+        return Ok(None);
+    }
+    let items = container
+        .get_digest(user.get_notification_preferences())
+        .into_iter()
+        .map(|n| n.get_subject().to_string())
+        .collect::<Vec<String>>();
+    if items.is_empty() {
+        return Ok(None);
+    }
+    let items_text = items
+        .iter()
+        .map(|i| format!("- {}", i))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let items_html = format!(
+        "<ul>{}</ul>",
+        items
+            .iter()
+            .map(|i| format!("<li>{}</li>", escape_html(i)))
+            .collect::<String>()
+    );
+    Ok(Some(
+        Template::NotificationDigest
+            .render(&[
+                ("name", user.get_user_name()),
+                ("count", &items.len().to_string()),
+                ("items", &items_text),
+                ("items_html", &items_html),
+                ("link", link),
+            ])?
+            .into_message(user.get_user_email())?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_digest_email() {
+        let mut user = User::new(
+            "demo".into(),
+            "Demo User".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        let mut preferences = NotificationPreferences::default();
+        preferences.set(NotificationCategory::Comment, DeliveryMode::DailyDigest);
+        user.set_notification_preferences(preferences);
+        let link = "https://gnstore.hu/notification";
+        // Opted in at 15:00, notified right after
+        let opt_in = Utc.ymd(2020, 3, 1).and_hms(15, 0, 0);
+        let mut container = NotificationContainer::new("demo".into());
+        assert_eq!(
+            digest_email(&container, &user, link, 7, opt_in).unwrap(),
+            None
+        );
+        container.start_digest(opt_in);
+        let mut comment = Notification::new("Új hozzászólás <b>".into());
+        comment.set_category(NotificationCategory::Comment);
+        container.add(comment);
+        container.add(Notification::new("Rendszer".into()));
+        // Not before the next digest hour
+        let evening = opt_in + Duration::hours(5);
+        assert_eq!(
+            digest_email(&container, &user, link, 7, evening).unwrap(),
+            None
+        );
+        let morning = Utc.ymd(2020, 3, 2).and_hms(7, 5, 0);
+        let message = digest_email(&container, &user, link, 7, morning)
+            .unwrap()
+            .unwrap();
+        let email = OutboxEmail::new(message);
+        assert_eq!(email.get_to(), "demo@user.com");
+        assert_eq!(
+            email.get_subject(),
+            "Gardenova napi összesítő: 1 új értesítés"
+        );
+        assert_eq!(email.get_body().contains("- Új hozzászólás <b>"), true);
+        assert_eq!(email.get_body().contains("Rendszer"), false);
+        assert_eq!(email.get_body().contains(link), true);
+        assert_eq!(
+            email
+                .get_message()
+                .unwrap()
+                .get_html()
+                .unwrap()
+                .contains("<li>Új hozzászólás &lt;b&gt;</li>"),
+            true
+        );
+        container.set_digest_sent(morning);
+        assert_eq!(
+            digest_email(&container, &user, link, 7, morning + Duration::hours(1)).unwrap(),
+            None
+        );
+    }
+}
@@ -15,11 +15,26 @@
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::config::number_from_env;
 use crate::error::Error::*;
 use crate::prelude::*;
 use bcrypt::{hash, verify};
 use rand::Rng;
 
+// Default bcrypt cost
+const DEFAULT_HASH_COST: u32 = 12;
+// bcrypt cost for generated secrets
+const SECRET_HASH_COST: u32 = 4;
+// Common passwords we never accept, one per line, lowercase
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+/// # Password hash cost
+/// bcrypt cost from ENV PASSWORD_HASH_COST, default is 12.
+/// Each +1 doubles the time of hashing and verifying.
+pub fn hash_cost() -> AppResult<u32> {
+    number_from_env("PASSWORD_HASH_COST", DEFAULT_HASH_COST, 4..=31)
+}
+
 /// # Hash password
 /// Get a password string pointer, returns a Result<String, String>
 /// ```rust
@@ -27,9 +42,7 @@ use rand::Rng;
 /// let hash = hash_password("purple dog").unwrap();
 /// ```
 pub fn hash_password(password: &str) -> AppResult<String> {
-    //let hashed = hash("hunter2", DEFAULT_COST)?;
-    //let valid = verify("hunter2", &hashed)?;
-    match hash(password, 6) {
+    match hash(password, hash_cost()?) {
         Ok(hash) => Ok(hash),
         Err(_) => Err(InternalError(
             "Error while creating hash from password".into(),
@@ -37,6 +50,20 @@ pub fn hash_password(password: &str) -> AppResult<String> {
     }
 }
 
+/// # Hash generated secret
+/// For random tokens we create, like refresh tokens, reset tokens
+/// and recovery codes. They are long random strings, so a low
+/// cost is enough, and we do not slow down every token refresh.
+/// Verify them with `verify_password_from_hash`.
+pub fn hash_secret(secret: &str) -> AppResult<String> {
+    match hash(secret, SECRET_HASH_COST) {
+        Ok(hash) => Ok(hash),
+        Err(_) => Err(InternalError(
+            "Error while creating hash from secret".into(),
+        )),
+    }
+}
+
 /// # Verify password from hash
 /// Gets a password and hash pointer and returns a Result<bool, String>
 /// True if verify succeed, false otherwise.
@@ -56,6 +83,27 @@ pub fn verify_password_from_hash<'a>(password: &'a str, hash: &'a str) -> AppRes
     }
 }
 
+/// # Needs rehash
+/// True if the hash was created with a lower cost
+/// than the current one, so it should be replaced
+/// at the next successful login.
+/// ```rust
+/// use core_lib::password::{hash_password, needs_rehash};
+/// let hash = hash_password("purple_dog").unwrap();
+/// assert_eq!(needs_rehash(&hash), false);
+/// ```
+pub fn needs_rehash(hash: &str) -> bool {
+    // bcrypt hash format: $2b$<cost>$<salt and hash>
+    let cost = match hash.split('$').nth(2) {
+        Some(cost) => cost.parse::<u32>().unwrap_or(0),
+        None => 0,
+    };
+    match hash_cost() {
+        Ok(current) => cost < current,
+        Err(_) => false,
+    }
+}
+
 /// # Generate random password
 /// Set a length or leave it None.
 /// Returns a random password aA-zZ, 0-9
@@ -92,54 +140,122 @@ pub fn generate_random_password(length: Option<u32>) -> AppResult<String> {
     Ok(password)
 }
 
-/// # Validate password
-/// Validate password to check it is strong enough.
-/// What we check is *password length*, *uppercase character frequency*,
-/// *lowercase character frequency* and *number frequency*.
-/// ```rust
-/// use core_lib::user::password::validate_password;
-/// assert_eq!(validate_password("DEmoPassWord1234789").is_ok(), true);
-/// ```
-pub fn validate_password(password: &str) -> AppResult<()> {
-    let min_password_len = 3;
-    let min_character_lowercase = 2;
-    let min_character_uppercase = 1;
-    let min_numeric_character = 1;
-    let mut character_lowercase: u32 = 0;
-    let mut character_uppercase: u32 = 0;
-    let mut character_numeric: u32 = 0;
-    for ch in password.chars() {
-        // count numeric characters
-        if ch.is_numeric() {
-            character_numeric += 1;
+/// # Password policy
+/// Rules a new password must meet.
+/// Loaded from ENV, see `PasswordPolicy::from_env()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_lowercase: usize,
+    pub min_uppercase: usize,
+    pub min_numeric: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: 10,
+            min_lowercase: 2,
+            min_uppercase: 1,
+            min_numeric: 1,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Load policy from ENV
+    /// PASSWORD_MIN_LENGTH, PASSWORD_MIN_LOWERCASE,
+    /// PASSWORD_MIN_UPPERCASE and PASSWORD_MIN_NUMERIC
+    /// are all optional, missing values use the default.
+    pub fn from_env() -> AppResult<Self> {
+        let default = PasswordPolicy::default();
+        Ok(PasswordPolicy {
+            min_length: number_from_env("PASSWORD_MIN_LENGTH", default.min_length, 1..)?,
+            min_lowercase: number_from_env("PASSWORD_MIN_LOWERCASE", default.min_lowercase, 0..)?,
+            min_uppercase: number_from_env("PASSWORD_MIN_UPPERCASE", default.min_uppercase, 0..)?,
+            min_numeric: number_from_env("PASSWORD_MIN_NUMERIC", default.min_numeric, 0..)?,
+        })
+    }
+    /// Validate password to check it is strong enough.
+    /// What we check is *password length*, *uppercase character frequency*,
+    /// *lowercase character frequency*, *number frequency*, and that
+    /// it is not a commonly used password.
+    pub fn validate(&self, password: &str) -> AppResult<()> {
+        let mut character_lowercase: usize = 0;
+        let mut character_uppercase: usize = 0;
+        let mut character_numeric: usize = 0;
+        for ch in password.chars() {
+            // count numeric characters
+            if ch.is_numeric() {
+                character_numeric += 1;
+            }
+            // count lowercase characters
+            if ch.is_lowercase() {
+                character_lowercase += 1;
+            }
+            // count uppercase characters
+            if ch.is_uppercase() {
+                character_uppercase += 1;
+            }
         }
-        // count lowercase characters
-        if ch.is_lowercase() {
-            character_lowercase += 1;
+        if password.chars().count() < self.min_length
+            || character_numeric < self.min_numeric
+            || character_lowercase < self.min_lowercase
+            || character_uppercase < self.min_uppercase
+        {
+            return Err(BadRequest(format!(
+                "A jelszó hossza min {} karakter legyen, és tartalmazzon legalább {} \
+                 kisbetűt, valamint legalább {} db nagybetűt, valamint legalább {} számot",
+                self.min_length, self.min_lowercase, self.min_uppercase, self.min_numeric
+            )));
         }
-        // count uppercase characters
-        if ch.is_uppercase() {
-            character_uppercase += 1;
+        if is_common_password(password) {
+            return Err(BadRequest(
+                "Túl gyakori jelszó, kérjük válasszon másikat".into(),
+            ));
         }
+        Ok(())
     }
-    if password.len() >= min_password_len
-        && character_numeric >= min_numeric_character
-        && character_lowercase >= min_character_lowercase
-        && character_uppercase >= min_character_uppercase
-    {
+    /// Validate password for a given user
+    /// Same as `validate`, but the password cannot
+    /// contain the user ID or the email address name part.
+    pub fn validate_for_user(&self, password: &str, userid: &str, email: &str) -> AppResult<()> {
+        self.validate(password)?;
+        let password = password.to_lowercase();
+        let email_name = email.split('@').next().unwrap_or("");
+        for part in &[userid, email_name] {
+            // Too short parts would match almost anything
+            if part.len() >= 3 && password.contains(&part.to_lowercase()) {
+                return Err(BadRequest(
+                    "A jelszó nem tartalmazhatja a felhasználói azonosítót vagy az email címet"
+                        .into(),
+                ));
+            }
+        }
         Ok(())
-    } else {
-        Err(BadRequest(format!(
-            "A jelszó hossza min {} karakter legyen, és tartalmazzon legalább {}
-            kisbetűt, valamint legalább {} db nagybetűt, valamint legalább {} számot",
-            min_password_len,
-            min_character_lowercase,
-            min_character_uppercase,
-            min_numeric_character
-        )))
     }
 }
 
+/// # Common password
+/// Check the password against the bundled
+/// common password list, case insensitive.
+pub fn is_common_password(password: &str) -> bool {
+    let password = password.to_lowercase();
+    COMMON_PASSWORDS
+        .lines()
+        .any(|common| common.trim() == password)
+}
+
+/// # Validate password
+/// Validate password against the password policy loaded from ENV.
+/// ```rust
+/// use core_lib::password::validate_password;
+/// assert_eq!(validate_password("DEmoPassWord1234789").is_ok(), true);
+/// ```
+pub fn validate_password(password: &str) -> AppResult<()> {
+    PasswordPolicy::from_env()?.validate(password)
+}
+
 // Tests
 #[cfg(test)]
 mod tests {
@@ -172,11 +288,55 @@ mod tests {
     #[test]
     fn test_validate_password() {
         assert_eq!(validate_password("pass").is_ok(), false); // should be err
-        assert_eq!(validate_password("PAss1").is_ok(), true); // should be err
-        assert_eq!(validate_password("password").is_ok(), false); // should be err
-        assert_eq!(validate_password("Password").is_ok(), false); // should be err
-        assert_eq!(validate_password("PASsword").is_ok(), false); // should be err
-        assert_eq!(validate_password("Password12").is_ok(), true); // should be err
-        assert_eq!(validate_password("PAssword12").is_ok(), true); // should be ok
+        assert_eq!(validate_password("PAss1").is_ok(), false); // too short
+        assert_eq!(validate_password("passwordpassword").is_ok(), false); // should be err
+        assert_eq!(validate_password("Passwordpassword").is_ok(), false); // should be err
+        assert_eq!(validate_password("PASswordpassword").is_ok(), false); // should be err
+        assert_eq!(validate_password("Password123").is_ok(), false); // common password
+        assert_eq!(validate_password("GreenHouse42").is_ok(), true); // should be ok
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let policy = PasswordPolicy {
+            min_length: 4,
+            min_lowercase: 0,
+            min_uppercase: 0,
+            min_numeric: 2,
+        };
+        assert_eq!(policy.validate("ab12").is_ok(), true);
+        assert_eq!(policy.validate("abc1").is_ok(), false);
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            policy
+                .validate_for_user("Demo_user2020", "demo_user", "x@y.com")
+                .is_ok(),
+            false
+        );
+        assert_eq!(
+            policy
+                .validate_for_user("MyJohnDoe2020", "demo", "johndoe@y.com")
+                .is_ok(),
+            false
+        );
+        assert_eq!(
+            policy
+                .validate_for_user("GreenHouse42", "demo", "johndoe@y.com")
+                .is_ok(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_common_password() {
+        assert_eq!(is_common_password("Qwertyuiop"), true);
+        assert_eq!(is_common_password("GreenHouse42"), false);
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let old_hash = hash("purple_dog", 6).unwrap();
+        assert_eq!(needs_rehash(&old_hash), true);
+        assert_eq!(needs_rehash(&hash_password("purple_dog").unwrap()), false);
     }
 }
@@ -0,0 +1,291 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email::Message;
+use crate::error::Error;
+use crate::error::Error::*;
+use crate::prelude::*;
+use serde::Serialize;
+use std::str::FromStr;
+
+const LAYOUT_HTML: &str = include_str!("../templates/layout.html");
+const LAYOUT_TEXT: &str = include_str!("../templates/layout.txt");
+
+/// Transactional email templates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    PasswordReset,
+    Welcome,
+    IssueAssigned,
+    OrderConfirmation,
+    Notification,
+    NotificationDigest,
+}
+
+/// Rendered email
+/// Subject with plain text and HTML body,
+/// both wrapped into the shared layout.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl RenderedEmail {
+    pub fn into_message(self, to: &str) -> AppResult<Message> {
+        Ok(Message::new(to, &self.subject, &self.text)?.with_html(self.html))
+    }
+}
+
+impl Template {
+    pub fn all() -> Vec<Template> {
+        vec![
+            Template::PasswordReset,
+            Template::Welcome,
+            Template::IssueAssigned,
+            Template::OrderConfirmation,
+            Template::Notification,
+            Template::NotificationDigest,
+        ]
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::PasswordReset => "password_reset",
+            Template::Welcome => "welcome",
+            Template::IssueAssigned => "issue_assigned",
+            Template::OrderConfirmation => "order_confirmation",
+            Template::Notification => "notification",
+            Template::NotificationDigest => "notification_digest",
+        }
+    }
+    fn subject(&self) -> &'static str {
+        match self {
+            Template::PasswordReset => "Gardenova jelszó visszaállítás",
+            Template::Welcome => "Üdvözlünk a Gardenova rendszerében",
+            Template::IssueAssigned => "Hozzárendeltek egy issue-hoz: {{issue_title}}",
+            Template::OrderConfirmation => "Rendelés visszaigazolás: {{order_id}}",
+            Template::Notification => "Gardenova értesítés: {{subject}}",
+            Template::NotificationDigest => "Gardenova napi összesítő: {{count}} új értesítés",
+        }
+    }
+    fn text(&self) -> &'static str {
+        match self {
+            Template::PasswordReset => include_str!("../templates/password_reset.txt"),
+            Template::Welcome => include_str!("../templates/welcome.txt"),
+            Template::IssueAssigned => include_str!("../templates/issue_assigned.txt"),
+            Template::OrderConfirmation => include_str!("../templates/order_confirmation.txt"),
+            Template::Notification => include_str!("../templates/notification.txt"),
+            Template::NotificationDigest => include_str!("../templates/notification_digest.txt"),
+        }
+    }
+    fn html(&self) -> &'static str {
+        match self {
+            Template::PasswordReset => include_str!("../templates/password_reset.html"),
+            Template::Welcome => include_str!("../templates/welcome.html"),
+            Template::IssueAssigned => include_str!("../templates/issue_assigned.html"),
+            Template::OrderConfirmation => include_str!("../templates/order_confirmation.html"),
+            Template::Notification => include_str!("../templates/notification.html"),
+            Template::NotificationDigest => {
+                include_str!("../templates/notification_digest.html")
+            }
+        }
+    }
+    /**
+     * Sample variables
+     * Used by the admin preview
+     */
+    pub fn sample_vars(&self) -> Vec<(&'static str, &'static str)> {
+        let link = ("link", "https://example.com/link");
+        match self {
+            Template::PasswordReset | Template::Welcome => vec![
+                ("name", "Minta Péter"),
+                ("username", "minta_peter"),
+                ("hours", "1"),
+                link,
+            ],
+            Template::IssueAssigned => vec![
+                ("name", "Minta Péter"),
+                ("assigned_by", "Kovács Anna"),
+                ("issue_title", "Hiányzó szállítólevél"),
+                link,
+            ],
+            Template::OrderConfirmation => vec![
+                ("name", "Minta Péter"),
+                ("order_id", "R-2020-0042"),
+                ("date", "2020-03-01"),
+                ("total", "12 500 Ft"),
+                link,
+            ],
+            Template::Notification => vec![
+                ("name", "Minta Péter"),
+                ("subject", "Hozzárendeltek a következő issue-hoz: Hiányzó szállítólevél"),
+                link,
+            ],
+            Template::NotificationDigest => vec![
+                ("name", "Minta Péter"),
+                ("count", "2"),
+                ("items", "- Új hozzászólás: Hiányzó szállítólevél\n- Új hozzászólás: Leltár"),
+                (
+                    "items_html",
+                    "<ul><li>Új hozzászólás: Hiányzó szállítólevél</li><li>Új hozzászólás: Leltár</li></ul>",
+                ),
+                link,
+            ],
+        }
+    }
+    /**
+     * Render template with variables
+     * Every {{variable}} must have a value. Values are
+     * HTML escaped in the HTML body, except variables
+     * named *_html, those are already HTML.
+     */
+    pub fn render(&self, vars: &[(&str, &str)]) -> AppResult<RenderedEmail> {
+        let subject = substitute(self.subject(), vars, false)?;
+        let text = substitute(self.text(), vars, false)?;
+        let html = substitute(self.html(), vars, true)?;
+        Ok(RenderedEmail {
+            text: substitute_owned(
+                LAYOUT_TEXT,
+                &[
+                    ("subject", subject.clone()),
+                    ("content", text.trim_end().to_string()),
+                ],
+            ),
+            html: substitute_owned(
+                LAYOUT_HTML,
+                &[
+                    ("subject", escape_html(&subject)),
+                    ("content", html.trim_end().to_string()),
+                ],
+            ),
+            subject,
+        })
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match Template::all().into_iter().find(|t| t.name() == name) {
+            Some(template) => Ok(template),
+            None => Err(BadRequest(format!("Ismeretlen email sablon: {}", name))),
+        }
+    }
+}
+
+/**
+ * Replace {{variable}} placeholders
+ * Returns error for unknown variables
+ */
+fn substitute(source: &str, vars: &[(&str, &str)], escape: bool) -> AppResult<String> {
+    let mut result = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = rest[start + 2..end].trim();
+        let value = match vars.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => value,
+            None => {
+                return Err(InternalError(format!(
+                    "Missing email template variable: {}",
+                    name
+                )))
+            }
+        };
+        result.push_str(&rest[..start]);
+        if escape && !name.ends_with("_html") {
+            result.push_str(&escape_html(value));
+        } else {
+            result.push_str(value);
+        }
+        rest = &rest[end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+// Layout variables are always set, and already escaped
+fn substitute_owned(source: &str, vars: &[(&str, String)]) -> String {
+    vars.iter()
+        .fold(source.to_string(), |result, (key, value)| {
+            result.replace(&format!("{{{{{}}}}}", key), value)
+        })
+}
+
+/**
+ * Escape text to use it inside HTML
+ */
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(
+            substitute("Hello {{ name }}!", &[("name", "World")], false).unwrap(),
+            "Hello World!"
+        );
+        assert_eq!(
+            substitute("{{name}}", &[("name", "<b>")], true).unwrap(),
+            "&lt;b&gt;"
+        );
+        assert_eq!(
+            substitute("{{list_html}}", &[("list_html", "<ul></ul>")], true).unwrap(),
+            "<ul></ul>"
+        );
+        assert_eq!(substitute("Hello {{name}}!", &[], false).is_ok(), false);
+    }
+
+    #[test]
+    fn test_render() {
+        let email = Template::IssueAssigned
+            .render(&[
+                ("name", "Demo User"),
+                ("assigned_by", "Admin"),
+                ("issue_title", "Broken <pipe>"),
+                ("link", "https://example.com/issue/1"),
+            ])
+            .unwrap();
+        assert_eq!(email.subject, "Hozzárendeltek egy issue-hoz: Broken <pipe>");
+        assert_eq!(email.text.contains("Broken <pipe>"), true);
+        assert_eq!(email.text.starts_with("Gardenova"), true);
+        assert_eq!(email.html.contains("Broken &lt;pipe&gt;"), true);
+        assert_eq!(email.html.contains("{{"), false);
+        assert_eq!(Template::Welcome.render(&[("name", "Demo")]).is_ok(), false);
+    }
+
+    #[test]
+    fn test_sample_vars() {
+        for template in Template::all() {
+            assert_eq!(template.render(&template.sample_vars()).is_ok(), true);
+            assert_eq!(template.name().parse::<Template>().unwrap(), template);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use chrono::prelude::*;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+
+/// # Throttle policy
+/// After `max_attempts` failed attempts the subject is locked.
+/// Every further failure doubles the lockout duration, starting
+/// from `base_lockout` up to `max_lockout`. Counters are reset
+/// after `reset_after` without any failure.
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottlePolicy {
+    pub max_attempts: u32,
+    pub base_lockout: Duration,
+    pub max_lockout: Duration,
+    pub reset_after: Duration,
+}
+
+impl ThrottlePolicy {
+    /// Policy for a user account
+    pub fn user() -> Self {
+        ThrottlePolicy {
+            max_attempts: 5,
+            base_lockout: Duration::seconds(30),
+            max_lockout: Duration::hours(1),
+            reset_after: Duration::hours(24),
+        }
+    }
+    /// Policy for a client IP address
+    /// More lenient, as many users can share the same IP.
+    pub fn ip() -> Self {
+        ThrottlePolicy {
+            max_attempts: 20,
+            base_lockout: Duration::seconds(30),
+            max_lockout: Duration::hours(1),
+            reset_after: Duration::hours(1),
+        }
+    }
+    /// Lockout duration after a given number of failures
+    pub fn lockout_for(&self, failed_count: u32) -> Option<Duration> {
+        if failed_count < self.max_attempts {
+            return None;
+        }
+        // Cap the exponent to avoid overflow
+        let exponent = std::cmp::min(failed_count - self.max_attempts, 16);
+        let lockout = self.base_lockout * 2i32.pow(exponent);
+        Some(std::cmp::min(lockout, self.max_lockout))
+    }
+}
+
+/// # Failed login attempts
+/// Stored per user, and kept in memory per client IP.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LoginAttempts {
+    failed_count: u32,
+    last_failed: Option<DateTime<Utc>>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginAttempts {
+    pub fn new() -> Self {
+        LoginAttempts::default()
+    }
+    pub fn get_failed_count(&self) -> u32 {
+        self.failed_count
+    }
+    pub fn get_last_failed(&self) -> Option<DateTime<Utc>> {
+        self.last_failed
+    }
+    pub fn get_locked_until(&self) -> Option<DateTime<Utc>> {
+        self.locked_until
+    }
+    /**
+     * Locked at a given time
+     */
+    pub fn is_locked(&self, now: DateTime<Utc>) -> bool {
+        match self.locked_until {
+            Some(until) => now < until,
+            None => false,
+        }
+    }
+    /**
+     * Nothing to remember anymore
+     * Not locked, and the last failure is older than `reset_after`,
+     * so the next failure would start from zero anyway.
+     */
+    pub fn is_expired(&self, policy: &ThrottlePolicy, now: DateTime<Utc>) -> bool {
+        if self.is_locked(now) {
+            return false;
+        }
+        match self.last_failed {
+            Some(last_failed) => now - last_failed > policy.reset_after,
+            None => true,
+        }
+    }
+    /**
+     * Register a failed attempt
+     * and lock if the policy says so
+     */
+    pub fn register_failure(&mut self, policy: &ThrottlePolicy, now: DateTime<Utc>) {
+        if let Some(last_failed) = self.last_failed {
+            if now - last_failed > policy.reset_after {
+                self.reset();
+            }
+        }
+        self.failed_count += 1;
+        self.last_failed = Some(now);
+        if let Some(lockout) = policy.lockout_for(self.failed_count) {
+            self.locked_until = Some(now + lockout);
+        }
+    }
+    /**
+     * Reset counters
+     * e.g. after a successful login or by an admin
+     */
+    pub fn reset(&mut self) {
+        self.failed_count = 0;
+        self.last_failed = None;
+        self.locked_until = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_for() {
+        let policy = ThrottlePolicy::user();
+        assert_eq!(policy.lockout_for(4), None);
+        assert_eq!(policy.lockout_for(5), Some(Duration::seconds(30)));
+        assert_eq!(policy.lockout_for(6), Some(Duration::seconds(60)));
+        assert_eq!(policy.lockout_for(7), Some(Duration::seconds(120)));
+        assert_eq!(policy.lockout_for(100), Some(Duration::hours(1)));
+    }
+
+    #[test]
+    fn test_register_failure() {
+        let policy = ThrottlePolicy::user();
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let mut attempts = LoginAttempts::new();
+        for _ in 0..4 {
+            attempts.register_failure(&policy, now);
+        }
+        assert_eq!(attempts.is_locked(now), false);
+        attempts.register_failure(&policy, now);
+        assert_eq!(attempts.is_locked(now), true);
+        assert_eq!(attempts.is_locked(now + Duration::seconds(31)), false);
+        // Next failure doubles the lockout
+        let later = now + Duration::seconds(31);
+        attempts.register_failure(&policy, later);
+        assert_eq!(attempts.is_locked(later + Duration::seconds(59)), true);
+        assert_eq!(attempts.is_locked(later + Duration::seconds(61)), false);
+        attempts.reset();
+        assert_eq!(attempts.get_failed_count(), 0);
+        assert_eq!(attempts.is_locked(later), false);
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let policy = ThrottlePolicy::ip();
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let mut attempts = LoginAttempts::new();
+        assert_eq!(attempts.is_expired(&policy, now), true);
+        attempts.register_failure(&policy, now);
+        assert_eq!(
+            attempts.is_expired(&policy, now + Duration::minutes(59)),
+            false
+        );
+        assert_eq!(
+            attempts.is_expired(&policy, now + Duration::minutes(61)),
+            true
+        );
+        // Locked longer than reset_after
+        let policy = ThrottlePolicy {
+            reset_after: Duration::seconds(10),
+            ..ThrottlePolicy::ip()
+        };
+        let mut attempts = LoginAttempts::new();
+        for _ in 0..20 {
+            attempts.register_failure(&policy, now);
+        }
+        assert_eq!(
+            attempts.is_expired(&policy, now + Duration::seconds(20)),
+            false
+        );
+        assert_eq!(
+            attempts.is_expired(&policy, now + Duration::seconds(31)),
+            true
+        );
+    }
+
+    #[test]
+    fn test_failures_expire() {
+        let policy = ThrottlePolicy::user();
+        let now = Utc.ymd(2020, 3, 1).and_hms(12, 0, 0);
+        let mut attempts = LoginAttempts::new();
+        for _ in 0..4 {
+            attempts.register_failure(&policy, now);
+        }
+        attempts.register_failure(&policy, now + Duration::days(2));
+        assert_eq!(attempts.get_failed_count(), 1);
+        assert_eq!(attempts.is_locked(now + Duration::days(2)), false);
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (C) 2020 Peter Mezei
+//
+// This file is part of GNStore.
+//
+// GNStore is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 2 of the License, or
+// (at your option) any later version.
+//
+// GNStore is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use rand::Rng;
+
+// RFC 4648 base32 alphabet
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+// Secret length in bytes, 160 bits as RFC 4226 recommends
+const SECRET_LEN: usize = 20;
+// Time step in seconds
+pub const TIME_STEP: i64 = 30;
+// Number of code digits
+pub const DIGITS: u32 = 6;
+
+/// # Base32 encode
+/// RFC 4648 without padding, as authenticator apps expect.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut result = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            let index = (buffer >> (bits - 5)) & 31;
+            result.push(BASE32_ALPHABET[index as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        let index = (buffer << (5 - bits)) & 31;
+        result.push(BASE32_ALPHABET[index as usize] as char);
+    }
+    result
+}
+
+/// # Base32 decode
+/// Case insensitive, ignores padding and spaces.
+/// Returns None for invalid characters.
+pub fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for ch in data.chars().filter(|c| *c != '=' && *c != ' ') {
+        let ch = ch.to_ascii_uppercase() as u8;
+        let value = BASE32_ALPHABET.iter().position(|c| *c == ch)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            result.push((buffer >> (bits - 8)) as u8);
+            bits -= 8;
+        }
+    }
+    Some(result)
+}
+
+/// # Generate secret
+/// Returns a new random secret base32 encoded
+pub fn generate_secret() -> String {
+    let mut rng = rand::thread_rng();
+    let secret: Vec<u8> = (0..SECRET_LEN).map(|_| rng.gen::<u8>()).collect();
+    base32_encode(&secret)
+}
+
+/// # HOTP
+/// RFC 4226 HMAC-SHA1 one-time password
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut hmac = Hmac::new(Sha1::new(), secret);
+    hmac.input(&counter.to_be_bytes());
+    let result = hmac.result();
+    let hash = result.code();
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = ((hash[offset] as u32 & 0x7f) << 24)
+        | ((hash[offset + 1] as u32) << 16)
+        | ((hash[offset + 2] as u32) << 8)
+        | (hash[offset + 3] as u32);
+    binary % 10u32.pow(digits)
+}
+
+/// Time step of a UNIX timestamp
+pub fn time_step(timestamp: i64) -> i64 {
+    timestamp / TIME_STEP
+}
+
+/// # TOTP
+/// RFC 6238 code for a given UNIX timestamp,
+/// zero padded to `digits` characters.
+pub fn totp(secret: &[u8], timestamp: i64, digits: u32) -> String {
+    format!(
+        "{:0width$}",
+        hotp(secret, time_step(timestamp) as u64, digits),
+        width = digits as usize
+    )
+}
+
+/// # Verify TOTP code
+/// Accepts the current, the previous and the next time step
+/// to tolerate clock drift. Returns the matched time step,
+/// so the caller can refuse to accept the same step twice.
+pub fn verify(secret_base32: &str, code: &str, timestamp: i64) -> Option<i64> {
+    let secret = base32_decode(secret_base32)?;
+    let code = code.trim().replace(" ", "");
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let step = time_step(timestamp);
+    for candidate in &[step - 1, step, step + 1] {
+        if *candidate >= 0 && totp(&secret, candidate * TIME_STEP, DIGITS) == code {
+            return Some(*candidate);
+        }
+    }
+    None
+}
+
+/// # otpauth URI
+/// Authenticator apps use it to enroll, usually as QR code.
+pub fn otpauth_uri(issuer: &str, account: &str, secret_base32: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = uri_encode(issuer),
+        account = uri_encode(account),
+        secret = secret_base32,
+        digits = DIGITS,
+        period = TIME_STEP
+    )
+}
+
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 Appendix B, SHA1 test vectors
+    #[test]
+    fn test_totp_rfc6238() {
+        let secret = b"12345678901234567890";
+        assert_eq!(totp(secret, 59, 8), "94287082");
+        assert_eq!(totp(secret, 1111111109, 8), "07081804");
+        assert_eq!(totp(secret, 1111111111, 8), "14050471");
+        assert_eq!(totp(secret, 1234567890, 8), "89005924");
+        assert_eq!(totp(secret, 2000000000, 8), "69279037");
+        assert_eq!(totp(secret, 20000000000, 8), "65353130");
+    }
+
+    #[test]
+    fn test_base32() {
+        // RFC 4648 test vectors without padding
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"fo"), "MZXQ");
+        assert_eq!(base32_encode(b"foo"), "MZXW6");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar".to_vec());
+        assert_eq!(
+            base32_decode("mzxw6ytboi======").unwrap(),
+            b"foobar".to_vec()
+        );
+        assert_eq!(base32_decode("MZ1W"), None);
+        let secret = generate_secret();
+        assert_eq!(base32_decode(&secret).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_verify() {
+        let secret = base32_encode(b"12345678901234567890");
+        let now = 1111111111;
+        let code = totp(b"12345678901234567890", now, DIGITS);
+        assert_eq!(verify(&secret, &code, now), Some(time_step(now)));
+        // Previous and next steps are accepted
+        assert_eq!(verify(&secret, &code, now + TIME_STEP).is_some(), true);
+        assert_eq!(verify(&secret, &code, now - TIME_STEP).is_some(), true);
+        // But not older ones
+        assert_eq!(verify(&secret, &code, now + 3 * TIME_STEP), None);
+        assert_eq!(verify(&secret, "000000x", now), None);
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        assert_eq!(
+            otpauth_uri("Gardenova", "demo user", "MZXW6"),
+            "otpauth://totp/Gardenova:demo%20user?secret=MZXW6&issuer=Gardenova&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
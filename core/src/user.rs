@@ -14,3 +14,83 @@
 //
 // You should have received a copy of the GNU General Public License
 // along with GNStore.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::email::Message;
+use crate::model::User;
+use crate::prelude::*;
+use crate::template::Template;
+use chrono::Duration;
+
+/// Password reset token lifetime in hours
+pub const RESET_TOKEN_HOURS: i64 = 1;
+
+/// # Request password reset
+/// Creates a single-use reset token for the user,
+/// and renders the reset email with the link
+/// pointing to the client (`client_url`).
+pub fn request_password_reset(user: &mut User, client_url: &str) -> AppResult<Message> {
+    let token = user.request_password_reset(Duration::hours(RESET_TOKEN_HOURS))?;
+    let link = format!("{}/password_reset?token={}", client_url, token);
+    Template::PasswordReset
+        .render(&[
+            ("name", user.get_user_name()),
+            ("username", user.get_user_id()),
+            ("hours", &RESET_TOKEN_HOURS.to_string()),
+            ("link", &link),
+        ])?
+        .into_message(user.get_user_email())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::{MailTransport, MemoryTransport};
+    use crate::model::OutboxEmail;
+    use chrono::prelude::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_password_reset_email() {
+        let mut user = User::new(
+            "demo".into(),
+            "Demo User".into(),
+            "demo@user.com".into(),
+            "".into(),
+            "".into(),
+        )
+        .unwrap();
+        // Worker holds the boxed clone, we keep the other one
+        let transport = Arc::new(MemoryTransport::new());
+        let worker_transport: Arc<dyn MailTransport> = transport.clone();
+        let message = request_password_reset(&mut user, "https://gnstore.hu").unwrap();
+        let mut email = OutboxEmail::new(message);
+        let result = email.send(worker_transport.as_ref());
+        email.register_delivery(&result, Utc::now());
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].get_to(), "demo@user.com");
+        assert_eq!(sent[0].get_subject(), "Gardenova jelszó visszaállítás");
+        let prefix = "https://gnstore.hu/password_reset?token=";
+        let line = sent[0]
+            .get_body()
+            .lines()
+            .find(|l| l.starts_with(prefix))
+            .expect("Reset link is missing");
+        let token = &line[prefix.len()..];
+        assert_eq!(sent[0].get_html().unwrap().contains(token), true);
+        // The emailed token resets the password, but only once
+        assert_eq!(
+            user.confirm_password_reset(token, "NewPassword12".into(), Utc::now())
+                .is_ok(),
+            true
+        );
+        assert_eq!(
+            user.confirm_password_reset(token, "OtherPassword12".into(), Utc::now())
+                .is_ok(),
+            false
+        );
+        // Outbox does not keep the link
+        assert_eq!(email.get_body().contains(token), false);
+    }
+}